@@ -0,0 +1,16 @@
+fn main()
+{
+    // The C harness is only linked into the integration tests, never into the library.
+    #[cfg(feature="ffi")]
+    {
+	println!("cargo:rerun-if-changed=include/malloc_array.h");
+	println!("cargo:rerun-if-changed=tests/ffi.c");
+	cc::Build::new()
+	    .file("tests/ffi.c")
+	    .include("include")
+	    .warnings(true)
+	    .cargo_metadata(false)
+	    .compile("malloc_array_ffi_test");
+	println!("cargo:rustc-link-arg-tests={}/libmalloc_array_ffi_test.a", std::env::var("OUT_DIR").unwrap());
+    }
+}
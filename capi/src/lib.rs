@@ -0,0 +1,2 @@
+// Re-exports the `#[no_mangle]` functions of `malloc_array::ffi` so they are exported from the cdylib and staticlib.
+pub use malloc_array::ffi::*;
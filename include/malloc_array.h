@@ -0,0 +1,42 @@
+/* C interface to malloc-array, built with the `ffi` feature.
+ * Link against the cdylib or staticlib built from capi/ (`cargo build -p malloc-array-capi`). */
+#ifndef MALLOC_ARRAY_H
+#define MALLOC_ARRAY_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* An array of `len` elements of `element_size` bytes each.
+ * The memory belongs to the crate's allocator and must be freed with `malloc_array_free()`. */
+typedef struct malloc_array {
+	void* data; /* NULL when empty */
+	size_t len;
+	size_t element_size;
+} malloc_array_t;
+
+/* Allocate an uninitialised array into `*out`. Returns 0 on success, -1 on failure. */
+int malloc_array_create(size_t element_size, size_t len, malloc_array_t* out);
+/* Allocate a zeroed array into `*out`. Returns 0 on success, -1 on failure. */
+int malloc_array_create_zeroed(size_t element_size, size_t len, malloc_array_t* out);
+/* Resize to `len` elements. Returns 0 on success, or -1 on failure leaving the array unchanged. */
+int malloc_array_resize(malloc_array_t* array, size_t len);
+/* Number of elements, or 0 if `array` is NULL. */
+size_t malloc_array_len(const malloc_array_t* array);
+/* Pointer to the elements, or NULL if empty or `array` is NULL. */
+void* malloc_array_data(const malloc_array_t* array);
+/* Free the memory and leave the array empty. Does nothing if `array` is NULL. */
+void malloc_array_free(malloc_array_t* array);
+/* Take the memory out of the array, leaving it empty. Writes the length to `*len` if it is not NULL.
+ * Give it back with `malloc_array_from_raw()` to free it. */
+void* malloc_array_into_raw(malloc_array_t* array, size_t* len);
+/* Create a descriptor for memory taken with `malloc_array_into_raw()`. */
+malloc_array_t malloc_array_from_raw(void* data, size_t element_size, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MALLOC_ARRAY_H */
@@ -7,15 +7,30 @@ use crate::{
     ptr::{self,VoidPointer,},
 };
 
-#[derive(Debug)]
-pub struct Error;
+/// An allocation failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error
+{
+    /// The allocator returned `NULL`.
+    Alloc,
+    /// The allocation would exceed a `Budget`.
+    Quota {
+	/// Bytes requested.
+	requested: usize,
+	/// Bytes left in the budget.
+	available: usize,
+    },
+}
 
 impl error::Error for Error{}
 impl fmt::Display for Error
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
     {
-	write!(f, "Allocation failed.")
+	match self {
+	    Self::Alloc => write!(f, "Allocation failed."),
+	    Self::Quota{requested, available} => write!(f, "Allocation of {} bytes exceeds budget ({} bytes available).", requested, available),
+	}
     }
 }
 
@@ -51,6 +66,28 @@ unsafe fn realloc_internal(ptr: *mut c_void, sz: libc::size_t) -> *mut c_void
     #[cfg(not(feature="jemalloc"))]
     return libc::realloc(ptr,sz);
 }
+#[inline]
+unsafe fn memalign_internal(align: libc::size_t, sz: libc::size_t) -> *mut c_void
+{
+    let mut ptr = NULL_PTR;
+    #[cfg(feature="jemalloc")]
+    let err = jemalloc_sys::posix_memalign(&mut ptr, align, sz);
+    #[cfg(not(feature="jemalloc"))]
+    let err = libc::posix_memalign(&mut ptr, align, sz);
+    if err == 0 {
+	ptr
+    } else {
+	NULL_PTR
+    }
+}
+#[inline]
+unsafe fn usable_size_internal(ptr: *mut c_void) -> libc::size_t
+{
+    #[cfg(feature="jemalloc")]
+    return jemalloc_sys::malloc_usable_size(ptr);
+    #[cfg(not(feature="jemalloc"))]
+    return libc::malloc_usable_size(ptr);
+}
 
 const NULL_PTR: *mut c_void = 0 as *mut c_void;
 
@@ -61,11 +98,21 @@ pub unsafe fn malloc(sz: usize) -> Result<VoidPointer,Error>
 	return Ok(ptr::NULL_PTR);
     }
     
-    match malloc_internal(sz as libc::size_t)
+    #[cfg(feature="fault_inject")]
+    if crate::fault::intercept(crate::fault::CallKind::Malloc, sz) {
+	return Err(Error::Alloc);
+    }
+
+    let result = match malloc_internal(sz as libc::size_t)
     {
-	null if null == NULL_PTR => Err(Error),
+	null if null == NULL_PTR => Err(Error::Alloc),
 	ptr => Ok(ptr as VoidPointer),
+    };
+    #[cfg(feature="trace")]
+    if let Ok(ptr) = result {
+	crate::trace::record(crate::trace::EventKind::Malloc, ptr, ptr::NULL_PTR, sz, 0);
     }
+    result
 }
 
 pub unsafe fn calloc(nm: usize, sz: usize) -> Result<VoidPointer, Error>
@@ -75,22 +122,38 @@ pub unsafe fn calloc(nm: usize, sz: usize) -> Result<VoidPointer, Error>
 	return Ok(ptr::NULL_PTR);
     }
     
-    match calloc_internal(nm as libc::size_t, sz as libc::size_t)
+    #[cfg(feature="fault_inject")]
+    if crate::fault::intercept(crate::fault::CallKind::Calloc, nm.saturating_mul(sz)) {
+	return Err(Error::Alloc);
+    }
+
+    let result = match calloc_internal(nm as libc::size_t, sz as libc::size_t)
     {
-	null if null == NULL_PTR => Err(Error),
+	null if null == NULL_PTR => Err(Error::Alloc),
 	ptr => Ok(ptr as VoidPointer),
+    };
+    #[cfg(feature="trace")]
+    if let Ok(ptr) = result {
+	crate::trace::record(crate::trace::EventKind::Calloc, ptr, ptr::NULL_PTR, nm*sz, 0);
     }
+    result
 }
 
 pub unsafe fn free(ptr: VoidPointer)
 {
     if ptr != crate::ptr::NULL_PTR {
+	#[cfg(feature="fault_inject")]
+	crate::fault::intercept(crate::fault::CallKind::Free, 0);
+	#[cfg(feature="trace")]
+	crate::trace::record(crate::trace::EventKind::Free, ptr::NULL_PTR, ptr, 0, 0);
 	free_internal(ptr as *mut c_void);
     }
 }
 
 pub unsafe fn realloc(ptr: VoidPointer, sz: usize) -> Result<VoidPointer, Error>
 {
+    #[cfg(feature="trace")]
+    let old = ptr;
     #[cfg(feature="zst_noalloc")]
     if sz == 0 {
 	free(ptr);
@@ -101,9 +164,66 @@ pub unsafe fn realloc(ptr: VoidPointer, sz: usize) -> Result<VoidPointer, Error>
 	return malloc(sz);
     }
     
-    match realloc_internal(ptr as *mut c_void, sz as libc::size_t)
+    #[cfg(feature="fault_inject")]
+    if crate::fault::intercept(crate::fault::CallKind::Realloc, sz) {
+	return Err(Error::Alloc);
+    }
+
+    let result = match realloc_internal(ptr as *mut c_void, sz as libc::size_t)
+    {
+	null if null == NULL_PTR => Err(Error::Alloc),
+	ptr => Ok(ptr as VoidPointer),
+    };
+    #[cfg(feature="trace")]
+    if let Ok(ptr) = result {
+	crate::trace::record(crate::trace::EventKind::Realloc, ptr, old, sz, 0);
+    }
+    result
+}
+
+/// Alignment `malloc()` guarantees. Larger alignments need `aligned_malloc()`.
+pub(crate) const MALLOC_ALIGN: usize = 2 * std::mem::size_of::<usize>();
+
+/// Allocate `sz` bytes aligned to `align`, which must be a power of two multiple of the pointer size. Freed with `free()`.
+pub unsafe fn aligned_malloc(align: usize, sz: usize) -> Result<VoidPointer, Error>
+{
+    #[cfg(feature="zst_noalloc")]
+    if sz == 0 {
+	return Ok(ptr::NULL_PTR);
+    }
+
+    #[cfg(feature="fault_inject")]
+    if crate::fault::intercept(crate::fault::CallKind::AlignedMalloc, sz) {
+	return Err(Error::Alloc);
+    }
+
+    let result = match memalign_internal(align as libc::size_t, sz as libc::size_t)
     {
-	null if null == NULL_PTR => Err(Error),
+	null if null == NULL_PTR => Err(Error::Alloc),
 	ptr => Ok(ptr as VoidPointer),
+    };
+    #[cfg(feature="trace")]
+    if let Ok(ptr) = result {
+	crate::trace::record(crate::trace::EventKind::AlignedMalloc, ptr, ptr::NULL_PTR, sz, align);
+    }
+    result
+}
+
+/// The number of bytes actually usable in an allocation, which may be more than were requested.
+pub unsafe fn usable_size(ptr: VoidPointer) -> usize
+{
+    if ptr == crate::ptr::NULL_PTR {
+	0
+    } else {
+	usable_size_internal(ptr as *mut c_void) as usize
+    }
+}
+
+/// The size of a memory page.
+pub fn page_size() -> usize
+{
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+	n if n > 0 => n as usize,
+	_ => 4096,
     }
 }
@@ -0,0 +1,420 @@
+use super::*;
+use std::{
+    cell::{
+	Cell,
+	RefCell,
+    },
+    marker::PhantomData,
+    mem,
+    cmp,
+};
+
+/// Default size of the chunks an `Arena` allocates.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A chunk of memory allocated with `malloc()`.
+#[derive(Debug)]
+struct Chunk
+{
+    ptr: *mut u8,
+    size: usize,
+}
+
+/// Bump allocator handing out `ArenaArray`s from large `malloc()`ed chunks.
+///
+/// Arrays borrow the arena, and all of the chunks are freed together when it is dropped. `reset()` reuses the chunks.
+#[derive(Debug)]
+pub struct Arena
+{
+    chunks: RefCell<Vec<Chunk>>,
+    current: Cell<usize>,
+    offset: Cell<usize>,
+    chunk_size: usize,
+}
+
+/// Array bump-allocated in an `Arena`. Dropping it drops the elements but does not free the memory.
+pub struct ArenaArray<'a, T>
+{
+    ptr: *mut T,
+    size: usize,
+
+    /// Call `drop()` on sub-elements when `drop`ping the array. This is not needed for types that implement `Copy`.
+    pub drop_check: bool,
+
+    _marker: PhantomData<(&'a Arena, T)>,
+}
+
+unsafe impl<T> Sync for ArenaArray<'_, T>
+where T: Sync{}
+unsafe impl<T> Send for ArenaArray<'_, T>
+where T: Send{}
+
+impl Arena
+{
+    /// Create a new arena. Does not allocate until used.
+    pub fn new() -> Self
+    {
+	Self::with_chunk_size(CHUNK_SIZE)
+    }
+
+    /// Create a new arena allocating chunks of at least `chunk_size` bytes.
+    pub fn with_chunk_size(chunk_size: usize) -> Self
+    {
+	Self {
+	    chunks: RefCell::new(Vec::new()),
+	    current: Cell::new(0),
+	    offset: Cell::new(0),
+	    chunk_size,
+	}
+    }
+
+    /// Total bytes allocated for chunks.
+    pub fn allocated_bytes(&self) -> usize
+    {
+	self.chunks.borrow().iter().map(|x| x.size).sum()
+    }
+
+    /// Bytes handed out since creation or the last `reset()`, including alignment padding and unused chunk tails.
+    pub fn used_bytes(&self) -> usize
+    {
+	let chunks = self.chunks.borrow();
+	chunks.iter().take(self.current.get()).map(|x| x.size).sum::<usize>() + self.offset.get()
+    }
+
+    /// Reuse all of the chunks for new arrays. No arrays can still be borrowing the arena.
+    pub fn reset(&mut self)
+    {
+	self.current.set(0);
+	self.offset.set(0);
+    }
+
+    /// Bump allocate `bytes` bytes aligned to `align`.
+    fn try_alloc_raw(&self, bytes: usize, align: usize) -> Result<*mut u8, Error>
+    {
+	if bytes == 0 {
+	    return Ok(align as *mut u8);
+	}
+	let mut chunks = self.chunks.borrow_mut();
+	loop {
+	    let current = self.current.get();
+	    if let Some(chunk) = chunks.get(current) {
+		let start = chunk.ptr as usize + self.offset.get();
+		let aligned = start.checked_add(align - 1).ok_or(Error::Alloc)? & !(align - 1);
+		let end = aligned.checked_add(bytes).ok_or(Error::Alloc)?;
+		if end <= chunk.ptr as usize + chunk.size {
+		    self.offset.set(end - chunk.ptr as usize);
+		    return Ok(aligned as *mut u8);
+		}
+		if current + 1 < chunks.len() {
+		    self.current.set(current + 1);
+		    self.offset.set(0);
+		    continue;
+		}
+	    }
+	    let size = cmp::max(self.chunk_size, bytes.checked_add(align).ok_or(Error::Alloc)?);
+	    let ptr = unsafe { alloc::malloc(size)? } as *mut u8;
+	    chunks.push(Chunk{ptr, size});
+	    self.current.set(chunks.len() - 1);
+	    self.offset.set(0);
+	}
+    }
+
+    /// Allocate uninitialised memory for `size` elements of `T`, or an error if allocation fails.
+    pub fn try_new_array_uninit<T>(&self, size: usize) -> Result<ArenaArray<'_, T>, Error>
+    {
+	let bytes = size.checked_mul(mem::size_of::<T>()).ok_or(Error::Alloc)?;
+	Ok(ArenaArray {
+	    ptr: self.try_alloc_raw(bytes, mem::align_of::<T>())? as *mut T,
+	    size,
+	    drop_check: true,
+	    _marker: PhantomData,
+	})
+    }
+
+    /// Allocate uninitialised memory for `size` elements of `T`.
+    pub fn new_array_uninit<T>(&self, size: usize) -> ArenaArray<'_, T>
+    {
+	self.try_new_array_uninit(size).expect("malloc()")
+    }
+
+    /// Allocate zeroed memory for `size` elements of `T`.
+    pub fn new_array<T>(&self, size: usize) -> ArenaArray<'_, T>
+    {
+	let array = self.new_array_uninit(size);
+	unsafe {
+	    ptr::memset(array.ptr as *mut u8, 0, array.len_bytes());
+	}
+	array
+    }
+
+    /// Allocate `size` elements, each a clone of `initial`.
+    pub fn new_array_repeat<T: Clone>(&self, initial: T, size: usize) -> ArenaArray<'_, T>
+    {
+	let mut array: ArenaArray<'_, T> = self.new_array_uninit(size);
+	// Grow the length as elements are written, so a panicking `clone()` only drops the initialised ones.
+	array.size = 0;
+	for i in 0..size {
+	    unsafe {
+		array.ptr.add(i).write(initial.clone());
+	    }
+	    array.size = i + 1;
+	}
+	array
+    }
+
+    /// Allocate an array with the elements copied from a slice.
+    pub fn array_from_slice<T: Copy>(&self, from: &[T]) -> ArenaArray<'_, T>
+    {
+	let mut array = self.new_array_uninit(from.len());
+	array.as_slice_mut().copy_from_slice(from);
+	array
+    }
+}
+
+impl Default for Arena
+{
+    fn default() -> Self
+    {
+	Self::new()
+    }
+}
+
+impl Drop for Arena
+{
+    fn drop(&mut self)
+    {
+	for chunk in self.chunks.get_mut().drain(..)
+	{
+	    unsafe {
+		alloc::free(chunk.ptr as VoidPointer);
+	    }
+	}
+    }
+}
+
+impl<'a, T> ArenaArray<'a, T>
+{
+    /// Number of elements in this instance.
+    pub fn len(&self) -> usize
+    {
+	self.size
+    }
+
+    /// Is this instance empty?
+    pub fn is_empty(&self) -> bool
+    {
+	self.size == 0
+    }
+
+    /// Size of memory of this instance in bytes.
+    pub fn len_bytes(&self) -> usize
+    {
+	mem::size_of::<T>() * self.size
+    }
+
+    /// As an immutable slice of `T`.
+    pub fn as_slice(&self) -> &[T]
+    {
+	unsafe{slice::from_raw_parts(self.ptr, self.size)}
+    }
+
+    /// As a mutable slice of `T`.
+    pub fn as_slice_mut(&mut self) -> &mut [T]
+    {
+	unsafe{slice::from_raw_parts_mut(self.ptr, self.size)}
+    }
+
+    /// As immutable raw pointer.
+    pub fn as_ptr(&self) -> *const T
+    {
+	self.ptr as *const T
+    }
+
+    /// As mutable raw pointer.
+    pub fn as_ptr_mut(&mut self) -> *mut T
+    {
+	self.ptr
+    }
+
+    /// An immutable slice of the memory.
+    pub fn memory(&self) -> &[u8]
+    {
+	unsafe{slice::from_raw_parts(self.ptr as *const u8, self.len_bytes())}
+    }
+
+    /// Immutable slice iterator for this instance
+    pub fn iter(&self) -> slice::Iter<'_, T>
+    {
+	self.as_slice().iter()
+    }
+
+    /// Mutable slice iterator for this instance
+    pub fn iter_mut(&mut self) -> slice::IterMut<'_, T>
+    {
+	self.as_slice_mut().iter_mut()
+    }
+
+    /// Replace the element at `index` with `value`, and `forget` the old one.
+    /// Useful with `Arena::new_array_uninit()`.
+    pub fn replace_and_forget(&mut self, index: usize, value: T)
+    {
+	assert!(index<self.len());
+	unsafe {
+	    std::ptr::write(self.ptr.add(index), value);
+	}
+    }
+
+    /// Clone the elements into a new `HeapArray<T>` that outlives the arena.
+    pub fn to_heap_array(&self) -> HeapArray<T>
+    where T: Clone
+    {
+	let mut output = HeapArray::new_uninit(self.len());
+	for (i, x) in self.iter().enumerate()
+	{
+	    output.replace_and_forget(i, x.clone());
+	}
+	output
+    }
+}
+
+impl<T> Drop for ArenaArray<'_, T>
+{
+    fn drop(&mut self)
+    {
+	if self.drop_check {
+	    unsafe {
+		std::ptr::drop_in_place(self.as_slice_mut());
+	    }
+	}
+    }
+}
+
+impl<T> Deref for ArenaArray<'_, T>
+{
+    type Target = [T];
+    fn deref(&self) -> &Self::Target
+    {
+	self.as_slice()
+    }
+}
+impl<T> DerefMut for ArenaArray<'_, T>
+{
+    fn deref_mut(&mut self) -> &mut <Self as Deref>::Target
+    {
+	self.as_slice_mut()
+    }
+}
+
+impl<T> AsRef<[T]> for ArenaArray<'_, T>
+{
+    fn as_ref(&self) -> &[T]
+    {
+	self.as_slice()
+    }
+}
+impl<T> AsMut<[T]> for ArenaArray<'_, T>
+{
+    fn as_mut(&mut self) -> &mut [T]
+    {
+	self.as_slice_mut()
+    }
+}
+
+impl<T, I> Index<I> for ArenaArray<'_, T>
+where I: SliceIndex<[T]>
+{
+    type Output = <I as SliceIndex<[T]>>::Output;
+    fn index(&self, index: I) -> &Self::Output
+    {
+	&self.as_slice()[index]
+    }
+}
+impl<T, I> IndexMut<I> for ArenaArray<'_, T>
+where I: SliceIndex<[T]>
+{
+    fn index_mut(&mut self, index: I) -> &mut <Self as Index<I>>::Output
+    {
+	&mut self.as_slice_mut()[index]
+    }
+}
+
+impl<T> fmt::Debug for ArenaArray<'_, T>
+where T: fmt::Debug
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	write!(f, "{}: {:?}", std::any::type_name::<Self>(), self.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn bump()
+    {
+	let arena = Arena::with_chunk_size(64);
+	let bytes = arena.array_from_slice(b"abc");
+	let mut ints = arena.new_array::<u64>(4);
+	ints[3] = 10;
+	let strings = arena.new_array_repeat("string".to_owned(), 3);
+	let empty = arena.new_array::<u128>(0);
+
+	assert_eq!(&bytes[..], b"abc");
+	assert_eq!(&ints[..], &[0, 0, 0, 10]);
+	assert_eq!(ints.as_ptr() as usize % mem::align_of::<u64>(), 0);
+	assert_eq!(&strings[..], &["string", "string", "string"]);
+	assert!(empty.is_empty());
+	assert_eq!(arena.allocated_bytes(), 64 + 80);
+	assert_eq!(strings.to_heap_array().len(), 3);
+    }
+
+    #[test]
+    fn repeat_panic()
+    {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	static CLONES: AtomicUsize = AtomicUsize::new(0);
+	static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+	struct Bomb;
+	impl Clone for Bomb
+	{
+	    fn clone(&self) -> Self
+	    {
+		if CLONES.fetch_add(1, Ordering::SeqCst) == 2 {
+		    panic!("clone");
+		}
+		Bomb
+	    }
+	}
+	impl Drop for Bomb
+	{
+	    fn drop(&mut self)
+	    {
+		DROPS.fetch_add(1, Ordering::SeqCst);
+	    }
+	}
+
+	let arena = Arena::new();
+	assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| { arena.new_array_repeat(Bomb, 5); })).is_err());
+	// The two clones written and `initial` itself.
+	assert_eq!(DROPS.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn reset()
+    {
+	let mut arena = Arena::with_chunk_size(32);
+	let first = arena.new_array::<u8>(32).as_ptr();
+	arena.new_array::<u8>(100);
+	let allocated = arena.allocated_bytes();
+	arena.reset();
+
+	assert_eq!(arena.used_bytes(), 0);
+	assert_eq!(arena.new_array::<u8>(32).as_ptr(), first);
+	arena.new_array::<u8>(100);
+	assert_eq!(arena.allocated_bytes(), allocated);
+    }
+}
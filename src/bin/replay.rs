@@ -0,0 +1,297 @@
+use malloc_array::trace::{
+    self,
+    Event,
+    EventKind,
+};
+use std::{
+    collections::HashMap,
+    ffi::c_void,
+    fs::File,
+    io::{
+	self,
+	BufReader,
+	Write,
+    },
+    time::{
+	Duration,
+	Instant,
+    },
+};
+
+/// An allocator to replay against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend
+{
+    Libc,
+    #[cfg(feature="jemalloc")]
+    Jemalloc,
+    Mmap,
+}
+
+const BACKENDS: &[Backend] = &[
+    Backend::Libc,
+    #[cfg(feature="jemalloc")]
+    Backend::Jemalloc,
+    Backend::Mmap,
+];
+
+fn page_size() -> usize
+{
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+	n if n > 0 => n as usize,
+	_ => 4096,
+    }
+}
+
+fn page_round(size: usize) -> usize
+{
+    size.max(1).div_ceil(page_size()) * page_size()
+}
+
+impl Backend
+{
+    fn name(&self) -> &'static str
+    {
+	match self {
+	    Self::Libc => "libc",
+	    #[cfg(feature="jemalloc")]
+	    Self::Jemalloc => "jemalloc",
+	    Self::Mmap => "mmap",
+	}
+    }
+
+    fn from_name(name: &str) -> Option<Self>
+    {
+	BACKENDS.iter().copied().find(|x| x.name() == name)
+    }
+
+    unsafe fn malloc(&self, size: usize) -> *mut c_void
+    {
+	match self {
+	    Self::Libc => libc::malloc(size),
+	    #[cfg(feature="jemalloc")]
+	    Self::Jemalloc => jemalloc_sys::malloc(size),
+	    Self::Mmap => match libc::mmap(std::ptr::null_mut(), page_round(size), libc::PROT_READ | libc::PROT_WRITE, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0) {
+		libc::MAP_FAILED => std::ptr::null_mut(),
+		ptr => ptr,
+	    },
+	}
+    }
+
+    unsafe fn calloc(&self, size: usize) -> *mut c_void
+    {
+	match self {
+	    Self::Libc => libc::calloc(1, size),
+	    #[cfg(feature="jemalloc")]
+	    Self::Jemalloc => jemalloc_sys::calloc(1, size),
+	    Self::Mmap => self.malloc(size),
+	}
+    }
+
+    unsafe fn aligned_malloc(&self, align: usize, size: usize) -> *mut c_void
+    {
+	let mut ptr = std::ptr::null_mut();
+	let err = match self {
+	    Self::Libc => libc::posix_memalign(&mut ptr, align, size),
+	    #[cfg(feature="jemalloc")]
+	    Self::Jemalloc => jemalloc_sys::posix_memalign(&mut ptr, align, size),
+	    Self::Mmap => return if align <= page_size() { self.malloc(size) } else { std::ptr::null_mut() },
+	};
+	if err == 0 { ptr } else { std::ptr::null_mut() }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut c_void, old_size: usize, size: usize) -> *mut c_void
+    {
+	match self {
+	    Self::Libc => libc::realloc(ptr, size),
+	    #[cfg(feature="jemalloc")]
+	    Self::Jemalloc => jemalloc_sys::realloc(ptr, size),
+	    Self::Mmap => match libc::mremap(ptr, page_round(old_size), page_round(size), libc::MREMAP_MAYMOVE) {
+		libc::MAP_FAILED => std::ptr::null_mut(),
+		ptr => ptr,
+	    },
+	}
+    }
+
+    unsafe fn free(&self, ptr: *mut c_void, size: usize)
+    {
+	match self {
+	    Self::Libc => libc::free(ptr),
+	    #[cfg(feature="jemalloc")]
+	    Self::Jemalloc => jemalloc_sys::free(ptr),
+	    Self::Mmap => {
+		libc::munmap(ptr, page_round(size));
+	    },
+	}
+    }
+}
+
+/// Results of replaying a trace against one backend.
+#[derive(Debug)]
+struct Report
+{
+    time: Duration,
+    peak_rss: usize,
+    peak_live: usize,
+}
+
+impl Report
+{
+    /// Fraction of the resident memory growth not accounted for by live allocations.
+    fn fragmentation(&self) -> f64
+    {
+	if self.peak_rss > self.peak_live {
+	    1.0 - self.peak_live as f64 / self.peak_rss as f64
+	} else {
+	    0.0
+	}
+    }
+}
+
+/// Resident set size of this process in bytes.
+fn current_rss() -> usize
+{
+    std::fs::read_to_string("/proc/self/statm").ok()
+	.and_then(|x| x.split_whitespace().nth(1)?.parse::<usize>().ok())
+	.map_or(0, |x| x * page_size())
+}
+
+/// Highest resident set size of this process in bytes.
+fn peak_rss() -> usize
+{
+    unsafe {
+	let mut usage: libc::rusage = std::mem::zeroed();
+	libc::getrusage(libc::RUSAGE_SELF, &mut usage);
+	usage.ru_maxrss as usize * 1024
+    }
+}
+
+/// Write to every page of `size` bytes from `from`, so the memory becomes resident as it would in the traced program.
+unsafe fn touch(ptr: *mut c_void, from: usize, size: usize)
+{
+    let ptr = ptr as *mut u8;
+    let mut at = from;
+    while at < size {
+	ptr.add(at).write_volatile(1);
+	at += page_size();
+    }
+}
+
+/// Replay `events` in order on this thread. Memory still live at the end of the trace is freed afterwards.
+fn replay(backend: Backend, events: &[Event]) -> io::Result<Report>
+{
+    let baseline = current_rss();
+    let mut live: HashMap<u64, (*mut c_void, usize)> = HashMap::with_capacity(events.len());
+    let (mut live_bytes, mut peak_live) = (0usize, 0usize);
+    let failed = || io::Error::other(format!("{}: allocation failed", backend.name()));
+
+    let start = Instant::now();
+    for event in events {
+	let size = event.size as usize;
+	unsafe {
+	    match event.kind {
+		EventKind::Free => if let Some((ptr, size)) = live.remove(&event.id) {
+		    backend.free(ptr, size);
+		    live_bytes -= size;
+		},
+		EventKind::Realloc if live.contains_key(&event.extra) => {
+		    let (old, old_size) = live.remove(&event.extra).unwrap();
+		    let ptr = backend.realloc(old, old_size, size);
+		    if ptr.is_null() {
+			return Err(failed());
+		    }
+		    touch(ptr, old_size, size);
+		    live.insert(event.id, (ptr, size));
+		    live_bytes = live_bytes - old_size + size;
+		},
+		kind => {
+		    let ptr = match kind {
+			EventKind::Calloc => backend.calloc(size),
+			EventKind::AlignedMalloc => backend.aligned_malloc(event.extra as usize, size),
+			_ => backend.malloc(size),
+		    };
+		    if ptr.is_null() {
+			return Err(failed());
+		    }
+		    touch(ptr, 0, size);
+		    live.insert(event.id, (ptr, size));
+		    live_bytes += size;
+		},
+	    }
+	}
+	peak_live = peak_live.max(live_bytes);
+    }
+    let time = start.elapsed();
+
+    for (_, (ptr, size)) in live {
+	unsafe {
+	    backend.free(ptr, size);
+	}
+    }
+    Ok(Report {
+	time,
+	peak_rss: peak_rss().saturating_sub(baseline),
+	peak_live,
+    })
+}
+
+/// Replay in a child process, so each backend starts from a fresh heap and peak RSS.
+fn replay_in_child(backend: Backend, events: &[Event]) -> io::Result<()>
+{
+    io::stdout().flush()?;
+    match unsafe { libc::fork() } {
+	-1 => Err(io::Error::last_os_error()),
+	0 => {
+	    let code = match replay(backend, events) {
+		Ok(report) => {
+		    println!("{:<10} {:>12.3} {:>16} {:>16} {:>13.1}%", backend.name(), report.time.as_secs_f64() * 1000.0, report.peak_rss / 1024, report.peak_live / 1024, report.fragmentation() * 100.0);
+		    0
+		},
+		Err(error) => {
+		    eprintln!("{}", error);
+		    1
+		},
+	    };
+	    io::stdout().flush().ok();
+	    unsafe { libc::_exit(code) }
+	},
+	pid => {
+	    let mut status = 0;
+	    if unsafe { libc::waitpid(pid, &mut status, 0) } == -1 {
+		return Err(io::Error::last_os_error());
+	    }
+	    if libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0 {
+		Ok(())
+	    } else {
+		Err(io::Error::other(format!("{}: replay failed", backend.name())))
+	    }
+	},
+    }
+}
+
+/// Replay an allocation trace recorded with `malloc_array::trace` against each backend, or the ones named.
+///
+/// Usage: `malloc-array-replay TRACE [BACKEND...]`, where backends are `libc`, `jemalloc` (with the `jemalloc` feature) and `mmap`.
+fn main() -> io::Result<()>
+{
+    let mut args = std::env::args().skip(1);
+    let path = match args.next() {
+	Some(path) => path,
+	None => {
+	    eprintln!("Usage: malloc-array-replay TRACE [BACKEND...]");
+	    eprintln!("Backends: {}", BACKENDS.iter().map(Backend::name).collect::<Vec<_>>().join(", "));
+	    std::process::exit(2);
+	},
+    };
+    let backends = args.map(|name| Backend::from_name(&name).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("unknown backend: {}", name))))
+	.collect::<io::Result<Vec<_>>>()?;
+    let backends = if backends.is_empty() { BACKENDS.to_vec() } else { backends };
+
+    let events = trace::read(BufReader::new(File::open(&path)?))?;
+    println!("{}: {} events", path, events.len());
+    println!("{:<10} {:>12} {:>16} {:>16} {:>14}", "backend", "time (ms)", "peak RSS (KiB)", "peak live (KiB)", "fragmentation");
+    for backend in backends {
+	replay_in_child(backend, &events)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,279 @@
+use super::*;
+use std::{
+    convert::TryFrom,
+    mem,
+};
+
+/// A single `T` in `malloc()`ed memory.
+///
+/// Zero-sized types are not allocated with `zst_noalloc`.
+pub struct HeapBox<T>
+{
+    ptr: *mut T,
+}
+
+unsafe impl<T> Sync for HeapBox<T>
+where T: Sync{}
+unsafe impl<T> Send for HeapBox<T>
+where T: Send{}
+
+impl<T> HeapBox<T>
+{
+    /// Move `value` into `malloc()`ed memory.
+    ///
+    /// # Errors
+    /// If allocation fails. `value` is dropped.
+    pub fn try_new(value: T) -> Result<Self, Error>
+    {
+	unsafe {
+	    let ptr = if mem::align_of::<T>() > alloc::MALLOC_ALIGN {
+		alloc::aligned_malloc(mem::align_of::<T>(), mem::size_of::<T>())?
+	    } else {
+		alloc::malloc(mem::size_of::<T>())?
+	    } as *mut T;
+	    if !ptr.is_null() {
+		ptr.write(value);
+	    } else {
+		mem::forget(value);
+	    }
+	    Ok(Self{ptr})
+	}
+    }
+
+    /// Move `value` into `malloc()`ed memory.
+    pub fn new(value: T) -> Self
+    {
+	Self::try_new(value).expect("malloc()")
+    }
+
+    /// Pointer to the value. `NULL` (from `zst_noalloc`) is replaced with a dangling pointer.
+    fn value_ptr(&self) -> *mut T
+    {
+	if self.ptr.is_null() {
+	    std::ptr::NonNull::dangling().as_ptr()
+	} else {
+	    self.ptr
+	}
+    }
+
+    /// Consumes the instance, moving the value out and freeing the memory.
+    pub fn into_inner(self) -> T
+    {
+	unsafe {
+	    let value = self.value_ptr().read();
+	    let ptr = self.into_raw();
+	    if !ptr.is_null() {
+		alloc::free(ptr as VoidPointer);
+	    }
+	    value
+	}
+    }
+
+    /// Consumes the instance, passing ownership of the value to C. Give it back with `from_raw()`, or free it with `free()` after dropping the value.
+    ///
+    /// The pointer is `NULL` for zero-sized types with `zst_noalloc`.
+    pub fn into_raw(self) -> *mut T
+    {
+	let ptr = self.ptr;
+	mem::forget(self);
+	ptr
+    }
+
+    /// Take ownership of a value in `malloc()`ed memory, such as one from `into_raw()`.
+    ///
+    /// # Safety
+    /// `ptr` must be allocated by this crate's allocator backend, point to an initialised `T`, and not be used again. It may be `NULL` only if `T` is zero-sized.
+    pub unsafe fn from_raw(ptr: *mut T) -> Self
+    {
+	Self{ptr}
+    }
+
+    /// Consumes the instance, returning an array of length 1 that owns the same memory.
+    pub fn into_heap_array(self) -> HeapArray<T>
+    {
+	unsafe { HeapArray::from_raw_parts(self.into_raw(), 1) }
+    }
+
+    /// Take the memory of an array of length 1.
+    ///
+    /// If the array's memory is not aligned for `T`, the value is moved into a new allocation instead.
+    ///
+    /// # Errors
+    /// The array is returned if its length is not 1.
+    pub fn from_heap_array(array: HeapArray<T>) -> Result<Self, HeapArray<T>>
+    {
+	if array.len() != 1 {
+	    return Err(array);
+	}
+	let ptr = array.into_raw_parts().0;
+	if (ptr as usize).is_multiple_of(mem::align_of::<T>()) {
+	    Ok(Self{ptr})
+	} else {
+	    unsafe {
+		let value = ptr.read_unaligned();
+		alloc::free(ptr as VoidPointer);
+		Ok(Self::new(value))
+	    }
+	}
+    }
+
+    /// Coerce or move into a `Box<T>`.
+    ///
+    /// With `assume_libc` the memory is passed on without copying, unless `jemalloc` is enabled too: Rust's allocator would free jemalloc memory with libc.
+    pub fn into_box(self) -> Box<T>
+    {
+	#[cfg(all(feature="assume_libc", not(feature="jemalloc")))]
+	unsafe {
+	    let ptr = self.value_ptr();
+	    mem::forget(self);
+	    Box::from_raw(ptr)
+	}
+	#[cfg(not(all(feature="assume_libc", not(feature="jemalloc"))))]
+	{
+	    Box::new(self.into_inner())
+	}
+    }
+}
+
+impl<T> Drop for HeapBox<T>
+{
+    fn drop(&mut self)
+    {
+	unsafe {
+	    std::ptr::drop_in_place(self.value_ptr());
+	    if !self.ptr.is_null() {
+		alloc::free(self.ptr as VoidPointer);
+	    }
+	}
+    }
+}
+
+impl<T> Deref for HeapBox<T>
+{
+    type Target = T;
+    fn deref(&self) -> &Self::Target
+    {
+	unsafe { &*self.value_ptr() }
+    }
+}
+impl<T> DerefMut for HeapBox<T>
+{
+    fn deref_mut(&mut self) -> &mut Self::Target
+    {
+	unsafe { &mut *self.value_ptr() }
+    }
+}
+
+impl<T> AsRef<T> for HeapBox<T>
+{
+    fn as_ref(&self) -> &T
+    {
+	self
+    }
+}
+impl<T> AsMut<T> for HeapBox<T>
+{
+    fn as_mut(&mut self) -> &mut T
+    {
+	self
+    }
+}
+
+impl<T> Clone for HeapBox<T>
+where T: Clone
+{
+    fn clone(&self) -> Self
+    {
+	Self::new(T::clone(self))
+    }
+}
+
+impl<T> Default for HeapBox<T>
+where T: Default
+{
+    fn default() -> Self
+    {
+	Self::new(T::default())
+    }
+}
+
+impl<T> From<HeapBox<T>> for HeapArray<T>
+{
+    fn from(from: HeapBox<T>) -> Self
+    {
+	from.into_heap_array()
+    }
+}
+
+impl<T> TryFrom<HeapArray<T>> for HeapBox<T>
+{
+    type Error = HeapArray<T>;
+    fn try_from(from: HeapArray<T>) -> Result<Self, Self::Error>
+    {
+	Self::from_heap_array(from)
+    }
+}
+
+impl<T> fmt::Debug for HeapBox<T>
+where T: fmt::Debug
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn boxed()
+    {
+	let mut boxed = HeapBox::new(String::from("hello"));
+	boxed.push('!');
+	assert_eq!(&*boxed.clone(), "hello!");
+	assert_eq!(format!("{:?}", boxed), "\"hello!\"");
+
+	let array = boxed.into_heap_array();
+	assert_eq!(&array[..], &["hello!"]);
+	let boxed = HeapBox::try_from(array).unwrap();
+	assert_eq!(*boxed.into_box(), "hello!");
+	assert_eq!(HeapBox::from_heap_array(heap![1, 2]).unwrap_err().len(), 2);
+    }
+
+    #[test]
+    fn raw()
+    {
+	unsafe extern "C" fn callback(data: *mut libc::c_void)
+	{
+	    *(data as *mut u32) += 1;
+	}
+
+	let raw = HeapBox::new(41u32).into_raw();
+	unsafe {
+	    callback(raw as *mut libc::c_void);
+	    assert_eq!(HeapBox::from_raw(raw).into_inner(), 42);
+	}
+	assert_eq!(*HeapBox::new(()), ());
+    }
+
+    #[test]
+    fn aligned()
+    {
+	#[repr(align(64))]
+	#[derive(Debug, Clone, PartialEq)]
+	struct Aligned(u8);
+
+	let boxed = HeapBox::new(Aligned(1));
+	assert_eq!(&*boxed as *const Aligned as usize % 64, 0);
+	let clone = boxed.clone();
+	assert_eq!(&*clone as *const Aligned as usize % 64, 0);
+	assert_eq!(*clone, Aligned(1));
+
+	let boxed = HeapBox::from_heap_array(boxed.into_heap_array()).unwrap();
+	assert_eq!(&*boxed as *const Aligned as usize % 64, 0);
+	assert_eq!(*boxed.into_box(), Aligned(1));
+    }
+}
@@ -0,0 +1,194 @@
+use super::*;
+use alloc::Error;
+use std::sync::{
+    Arc,
+    atomic::{
+	AtomicUsize,
+	Ordering,
+    },
+};
+
+/// A byte quota that allocations are charged against. Share it between arrays and stores with an `Arc`.
+///
+/// Bytes are credited back when the memory charged for them is freed.
+#[derive(Debug)]
+pub struct Budget
+{
+    limit: usize,
+    used: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl Budget
+{
+    /// Create a new budget allowing up to `limit` bytes.
+    pub fn new(limit: usize) -> Self
+    {
+	Self {
+	    limit,
+	    used: AtomicUsize::new(0),
+	    peak: AtomicUsize::new(0),
+	}
+    }
+
+    /// The maximum number of bytes that can be charged.
+    pub fn limit(&self) -> usize
+    {
+	self.limit
+    }
+
+    /// The number of bytes currently charged.
+    pub fn used(&self) -> usize
+    {
+	self.used.load(Ordering::Acquire)
+    }
+
+    /// The number of bytes that can still be charged.
+    pub fn available(&self) -> usize
+    {
+	self.limit.saturating_sub(self.used())
+    }
+
+    /// The highest number of bytes charged at once.
+    pub fn peak(&self) -> usize
+    {
+	self.peak.load(Ordering::Acquire)
+    }
+
+    /// Reset the peak usage to the current usage.
+    pub fn reset_peak(&self)
+    {
+	self.peak.store(self.used(), Ordering::Release);
+    }
+
+    /// Charge `bytes` against the budget.
+    ///
+    /// # Errors
+    /// `Error::Quota` if the budget does not have `bytes` available.
+    pub fn try_charge(&self, bytes: usize) -> Result<(), Error>
+    {
+	let mut used = self.used();
+	loop {
+	    let next = match used.checked_add(bytes) {
+		Some(next) if next <= self.limit => next,
+		_ => return Err(Error::Quota {
+		    requested: bytes,
+		    available: self.limit.saturating_sub(used),
+		}),
+	    };
+	    match self.used.compare_exchange_weak(used, next, Ordering::AcqRel, Ordering::Acquire) {
+		Ok(_) => {
+		    self.peak.fetch_max(next, Ordering::AcqRel);
+		    return Ok(());
+		},
+		Err(current) => used = current,
+	    }
+	}
+    }
+
+    /// Credit `bytes` back to the budget. Crediting more than is charged leaves nothing charged.
+    pub fn credit(&self, bytes: usize)
+    {
+	let _ = self.used.fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| Some(used.saturating_sub(bytes)));
+    }
+}
+
+/// Bytes charged against a `Budget`, credited back when dropped.
+#[derive(Debug)]
+pub(crate) struct Charge
+{
+    budget: Arc<Budget>,
+    bytes: usize,
+}
+
+impl Charge
+{
+    /// Charge `bytes` against `budget`.
+    pub fn new(budget: &Arc<Budget>, bytes: usize) -> Result<Self, Error>
+    {
+	budget.try_charge(bytes)?;
+	Ok(Self {
+	    budget: Arc::clone(budget),
+	    bytes,
+	})
+    }
+
+    /// Change the number of bytes charged, charging or crediting the difference.
+    pub fn resize(&mut self, bytes: usize) -> Result<(), Error>
+    {
+	if bytes > self.bytes {
+	    self.budget.try_charge(bytes - self.bytes)?;
+	} else {
+	    self.budget.credit(self.bytes - bytes);
+	}
+	self.bytes = bytes;
+	Ok(())
+    }
+
+    /// Take over the bytes of `other`, if it is charged against the same budget. Otherwise `other` is credited back.
+    pub fn absorb(&mut self, other: Charge)
+    {
+	if Arc::ptr_eq(&self.budget, &other.budget) {
+	    self.bytes += other.bytes;
+	    std::mem::forget(other);
+	}
+    }
+
+    /// The budget charged against.
+    pub fn budget(&self) -> &Arc<Budget>
+    {
+	&self.budget
+    }
+}
+
+impl Drop for Charge
+{
+    fn drop(&mut self)
+    {
+	self.budget.credit(self.bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use store::Store;
+
+    #[test]
+    fn quota()
+    {
+	let budget = Arc::new(Budget::new(100));
+	let mut array = HeapArray::<u32>::try_new_in(20, &budget).unwrap();
+	assert_eq!(budget.used(), 80);
+	assert_eq!(HeapArray::<u8>::try_new_uninit_in(21, &budget).unwrap_err(), Error::Quota{requested: 21, available: 20});
+
+	assert!(array.try_resize(30).is_err());
+	assert_eq!(array.len(), 20);
+	array.try_resize(25).unwrap();
+	assert_eq!(budget.used(), 100);
+
+	drop(array);
+	assert_eq!((budget.used(), budget.peak()), (0, 100));
+	budget.credit(1);
+	assert_eq!(budget.available(), 100);
+    }
+
+    #[test]
+    fn store()
+    {
+	let budget = Arc::new(Budget::new(24));
+	let mut store = Store::with_budget(&budget);
+	store.try_alloc(1u64).unwrap();
+	store.try_alloc_slice_copy(&[2, 3]).unwrap();
+	assert!(store.try_alloc(4u64).is_err());
+	assert_eq!(budget.available(), 0);
+
+	let array = store.into_heap_array();
+	assert_eq!(&array[..], &[1, 2, 3]);
+	assert_eq!(budget.used(), 24);
+	assert!(Arc::ptr_eq(array.budget().unwrap(), &budget));
+	drop(array);
+	assert_eq!(budget.used(), 0);
+    }
+}
@@ -0,0 +1,287 @@
+use super::*;
+use std::{
+    borrow::Cow,
+    convert::TryFrom,
+    error,
+    ffi::{
+	CStr,
+	CString,
+    },
+    os::raw::c_char,
+    str::Utf8Error,
+};
+
+/// A NUL-terminated string in `malloc()`ed memory, for passing to C.
+///
+/// There is always exactly one NUL byte, at the end.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct HeapCString
+{
+    inner: HeapArray<u8>,
+}
+
+/// Bytes used to create a `HeapCString` contained a NUL byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NulError
+{
+    position: usize,
+}
+
+impl NulError
+{
+    /// The position of the first NUL byte.
+    pub fn nul_position(&self) -> usize
+    {
+	self.position
+    }
+}
+
+impl error::Error for NulError{}
+impl fmt::Display for NulError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	write!(f, "NUL byte found at position {}.", self.position)
+    }
+}
+
+impl HeapCString
+{
+    /// Create a new C string from bytes, appending the NUL terminator.
+    ///
+    /// # Errors
+    /// If `bytes` contains a NUL byte.
+    pub fn new<U: AsRef<[u8]>>(bytes: U) -> Result<Self, NulError>
+    {
+	let bytes = bytes.as_ref();
+	Self::check(bytes)?;
+	Ok(unsafe { Self::from_bytes_unchecked(bytes) })
+    }
+
+    /// Check that `bytes` contains no NUL byte.
+    pub(crate) fn check(bytes: &[u8]) -> Result<(), NulError>
+    {
+	match bytes.iter().position(|&x| x == 0) {
+	    Some(position) => Err(NulError{position}),
+	    None => Ok(()),
+	}
+    }
+
+    /// Wrap an array whose only NUL byte is the last one.
+    pub(crate) unsafe fn from_heap_array_unchecked(inner: HeapArray<u8>) -> Self
+    {
+	Self {
+	    inner,
+	}
+    }
+
+    /// Create a new C string from bytes that contain no NUL byte, appending the NUL terminator.
+    ///
+    /// # Safety
+    /// `bytes` must not contain a NUL byte.
+    pub unsafe fn from_bytes_unchecked(bytes: &[u8]) -> Self
+    {
+	let mut inner = HeapArray::new_uninit(bytes.len() + 1);
+	inner[..bytes.len()].copy_from_slice(bytes);
+	inner[bytes.len()] = 0;
+	Self {
+	    inner,
+	}
+    }
+
+    /// Number of bytes, not including the NUL terminator.
+    pub fn len(&self) -> usize
+    {
+	self.inner.len() - 1
+    }
+
+    /// Is the string empty apart from the NUL terminator?
+    pub fn is_empty(&self) -> bool
+    {
+	self.len() == 0
+    }
+
+    /// The bytes, not including the NUL terminator.
+    pub fn as_bytes(&self) -> &[u8]
+    {
+	&self.inner[..self.len()]
+    }
+
+    /// The bytes, including the NUL terminator.
+    pub fn as_bytes_with_nul(&self) -> &[u8]
+    {
+	&self.inner[..]
+    }
+
+    /// As a borrowed `CStr`.
+    pub fn as_c_str(&self) -> &CStr
+    {
+	unsafe { CStr::from_bytes_with_nul_unchecked(self.as_bytes_with_nul()) }
+    }
+
+    /// Pointer to the NUL-terminated string, valid for as long as this instance.
+    pub fn as_ptr(&self) -> *const c_char
+    {
+	self.inner.as_ptr() as *const c_char
+    }
+
+    /// The string as `str`, if it is valid UTF-8.
+    pub fn to_str(&self) -> Result<&str, Utf8Error>
+    {
+	std::str::from_utf8(self.as_bytes())
+    }
+
+    /// The string as UTF-8, with invalid sequences replaced with `U+FFFD`.
+    pub fn to_string_lossy(&self) -> Cow<'_, str>
+    {
+	String::from_utf8_lossy(self.as_bytes())
+    }
+
+    /// Consumes the instance, returning the bytes as a `String` if they are valid UTF-8.
+    ///
+    /// # Errors
+    /// The instance is returned with the UTF-8 error if they are not.
+    pub fn into_string(self) -> Result<String, (Self, Utf8Error)>
+    {
+	match self.to_str() {
+	    Ok(string) => Ok(string.to_owned()),
+	    Err(err) => Err((self, err)),
+	}
+    }
+
+    /// Consumes the instance, returning the bytes including the NUL terminator.
+    pub fn into_heap_array(self) -> HeapArray<u8>
+    {
+	self.inner
+    }
+
+    /// Consumes the instance, passing ownership of the string to C. It must be freed with `free()`, or given back with `from_raw()`.
+    ///
+    /// With the `jemalloc` feature this is jemalloc's `free()`.
+    pub fn into_raw(self) -> *mut c_char
+    {
+	self.inner.into_raw_parts().0 as *mut c_char
+    }
+
+    /// Take ownership of a NUL-terminated string allocated with `malloc()`, such as one from `into_raw()` or `strdup()`.
+    ///
+    /// # Safety
+    /// `ptr` must be a NUL-terminated string allocated by this crate's allocator backend, and not used again.
+    pub unsafe fn from_raw(ptr: *mut c_char) -> Self
+    {
+	let len = libc::strlen(ptr);
+	Self {
+	    inner: HeapArray::from_raw_parts(ptr as *mut u8, len + 1),
+	}
+    }
+}
+
+impl Deref for HeapCString
+{
+    type Target = CStr;
+    fn deref(&self) -> &Self::Target
+    {
+	self.as_c_str()
+    }
+}
+
+impl AsRef<CStr> for HeapCString
+{
+    fn as_ref(&self) -> &CStr
+    {
+	self.as_c_str()
+    }
+}
+
+impl Borrow<CStr> for HeapCString
+{
+    fn borrow(&self) -> &CStr
+    {
+	self.as_c_str()
+    }
+}
+
+impl From<&CStr> for HeapCString
+{
+    fn from(from: &CStr) -> Self
+    {
+	unsafe { Self::from_bytes_unchecked(from.to_bytes()) }
+    }
+}
+
+impl From<CString> for HeapCString
+{
+    fn from(from: CString) -> Self
+    {
+	Self::from(from.as_c_str())
+    }
+}
+
+impl From<HeapCString> for CString
+{
+    fn from(from: HeapCString) -> Self
+    {
+	from.as_c_str().to_owned()
+    }
+}
+
+impl TryFrom<&str> for HeapCString
+{
+    type Error = NulError;
+    fn try_from(from: &str) -> Result<Self, Self::Error>
+    {
+	Self::new(from)
+    }
+}
+
+impl TryFrom<String> for HeapCString
+{
+    type Error = NulError;
+    fn try_from(from: String) -> Result<Self, Self::Error>
+    {
+	Self::new(from)
+    }
+}
+
+impl fmt::Debug for HeapCString
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	write!(f, "{}: {:?}", std::any::type_name::<Self>(), self.as_c_str())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn nul()
+    {
+	let string = HeapCString::new("hello").unwrap();
+	assert_eq!(string.as_bytes_with_nul(), b"hello\0");
+	assert_eq!(string.len(), 5);
+	assert_eq!(unsafe { libc::strlen(string.as_ptr()) }, 5);
+	assert_eq!(HeapCString::new(b"he\0llo").unwrap_err().nul_position(), 2);
+	assert!(HeapCString::new("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn conversions()
+    {
+	let string = HeapCString::from(CString::new("abc").unwrap());
+	assert_eq!(string.as_c_str(), CStr::from_bytes_with_nul(b"abc\0").unwrap());
+	assert_eq!(CString::from(string.clone()).as_bytes(), b"abc");
+	assert_eq!(string.to_str(), Ok("abc"));
+
+	let raw = string.into_raw();
+	let string = unsafe { HeapCString::from_raw(raw) };
+	assert_eq!(string.into_string().unwrap(), "abc");
+
+	let invalid = HeapCString::new(b"a\xffb").unwrap();
+	assert!(invalid.to_str().is_err());
+	assert_eq!(invalid.to_string_lossy(), "a\u{FFFD}b");
+	assert!(invalid.into_string().is_err());
+    }
+}
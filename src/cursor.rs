@@ -0,0 +1,245 @@
+use super::*;
+use std::{
+    io::{
+	self,
+	Read,Write,
+	Seek,SeekFrom,
+	BufRead,
+    },
+    cmp,
+    convert::TryFrom,
+};
+
+/// An in-memory file over a `HeapArray<u8>`. Writing past the end grows the allocation with `realloc()`.
+#[derive(Debug)]
+pub struct HeapCursor
+{
+    inner: HeapArray<u8>,
+    len: usize,
+    pos: u64,
+}
+
+impl HeapCursor
+{
+    /// Create a new empty cursor. Does not allocate until written to.
+    pub fn new() -> Self
+    {
+	Self::from(HeapArray::new_uninit(0))
+    }
+
+    /// Create a new empty cursor with at least `capacity` bytes allocated.
+    pub fn with_capacity(capacity: usize) -> Self
+    {
+	Self {
+	    inner: heap![unsafe u8; capacity],
+	    len: 0,
+	    pos: 0,
+	}
+    }
+
+    /// The current position of the cursor.
+    pub fn position(&self) -> u64
+    {
+	self.pos
+    }
+
+    /// Set the position of the cursor. It may be set past the end of the data.
+    pub fn set_position(&mut self, pos: u64)
+    {
+	self.pos = pos;
+    }
+
+    /// Number of bytes of data in the cursor.
+    pub fn len(&self) -> usize
+    {
+	self.len
+    }
+
+    /// Is there no data in the cursor?
+    pub fn is_empty(&self) -> bool
+    {
+	self.len == 0
+    }
+
+    /// Number of bytes currently allocated.
+    pub fn capacity(&self) -> usize
+    {
+	self.inner.len()
+    }
+
+    /// The data in the cursor.
+    pub fn get_ref(&self) -> &[u8]
+    {
+	&self.inner[..self.len]
+    }
+
+    /// The data in the cursor, mutably.
+    pub fn get_mut(&mut self) -> &mut [u8]
+    {
+	&mut self.inner[..self.len]
+    }
+
+    /// Consumes the instance, returning the underlying array shrunk to the length of the data.
+    pub fn into_inner(self) -> HeapArray<u8>
+    {
+	if self.inner.len() != self.len {
+	    self.inner.resize(self.len)
+	} else {
+	    self.inner
+	}
+    }
+
+    /// Make sure at least `size` bytes are allocated, growing geometrically.
+    fn reserve_to(&mut self, size: usize)
+    {
+	if size > self.inner.len() {
+	    let size = cmp::max(size, self.inner.len() * 2);
+	    let inner = std::mem::replace(&mut self.inner, HeapArray::new_uninit(0));
+	    self.inner = inner.resize(size);
+	}
+    }
+}
+
+impl Default for HeapCursor
+{
+    fn default() -> Self
+    {
+	Self::new()
+    }
+}
+
+impl From<HeapArray<u8>> for HeapCursor
+{
+    fn from(inner: HeapArray<u8>) -> Self
+    {
+	Self {
+	    len: inner.len(),
+	    inner,
+	    pos: 0,
+	}
+    }
+}
+
+impl From<HeapCursor> for HeapArray<u8>
+{
+    fn from(cursor: HeapCursor) -> Self
+    {
+	cursor.into_inner()
+    }
+}
+
+impl AsRef<[u8]> for HeapCursor
+{
+    fn as_ref(&self) -> &[u8]
+    {
+	self.get_ref()
+    }
+}
+
+impl Read for HeapCursor
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+	let read = self.fill_buf()?.read(buf)?;
+	self.consume(read);
+	Ok(read)
+    }
+}
+
+impl BufRead for HeapCursor
+{
+    fn fill_buf(&mut self) -> io::Result<&[u8]>
+    {
+	let start = cmp::min(self.pos, self.len as u64) as usize;
+	Ok(&self.get_ref()[start..])
+    }
+
+    fn consume(&mut self, amt: usize)
+    {
+	self.pos += amt as u64;
+    }
+}
+
+impl Write for HeapCursor
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+	let pos = usize::try_from(self.pos).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "cursor position exceeds addressable memory"))?;
+	let end = pos.checked_add(buf.len()).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "write would overflow the cursor"))?;
+	self.reserve_to(end);
+	if pos > self.len {
+	    self.inner[self.len..pos].fill(0);
+	}
+	self.inner[pos..end].copy_from_slice(buf);
+	self.len = cmp::max(self.len, end);
+	self.pos = end as u64;
+	Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+	Ok(())
+    }
+}
+
+impl Seek for HeapCursor
+{
+    fn seek(&mut self, style: SeekFrom) -> io::Result<u64>
+    {
+	let (base, offset) = match style {
+	    SeekFrom::Start(n) => {
+		self.pos = n;
+		return Ok(n);
+	    },
+	    SeekFrom::End(n) => (self.len as u64, n),
+	    SeekFrom::Current(n) => (self.pos, n),
+	};
+	match base.checked_add_signed(offset) {
+	    Some(n) => {
+		self.pos = n;
+		Ok(n)
+	    },
+	    None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    #[test]
+    fn write_read()
+    {
+	let mut cursor = HeapCursor::new();
+	cursor.write_all(b"hello ").unwrap();
+	cursor.write_all(b"world").unwrap();
+	assert_eq!(cursor.get_ref(), b"hello world");
+
+	cursor.seek(SeekFrom::Start(6)).unwrap();
+	let mut string = String::new();
+	cursor.read_to_string(&mut string).unwrap();
+	assert_eq!(string, "world");
+
+	let array = HeapArray::from(cursor);
+	assert_eq!(array.len(), 11);
+	assert_eq!(&array[..], b"hello world");
+    }
+    #[test]
+    fn seek_past_end()
+    {
+	let mut cursor = HeapCursor::from(heap![1u8, 2, 3]);
+	assert_eq!(cursor.seek(SeekFrom::End(2)).unwrap(), 5);
+	cursor.write_all(&[6]).unwrap();
+	assert_eq!(cursor.get_ref(), &[1,2,3,0,0,6]);
+	assert!(cursor.seek(SeekFrom::Current(-7)).is_err());
+    }
+    #[test]
+    fn buf_read()
+    {
+	let mut cursor = HeapCursor::from(heap![unsafe b'a', b'\n', b'b']);
+	let lines: Vec<String> = (&mut cursor).lines().map(Result::unwrap).collect();
+	assert_eq!(lines, ["a", "b"]);
+	assert_eq!(cursor.fill_buf().unwrap(), &[]);
+    }
+}
@@ -0,0 +1,218 @@
+use super::*;
+use std::cell::RefCell;
+
+/// A call into the allocator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CallKind
+{
+    /// `malloc()`
+    Malloc,
+    /// `calloc()`
+    Calloc,
+    /// `realloc()`
+    Realloc,
+    /// `posix_memalign()`
+    AlignedMalloc,
+    /// `free()`
+    Free,
+}
+
+/// A call into the allocator recorded by an installed `Injector`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Call
+{
+    /// Which function was called.
+    pub kind: CallKind,
+    /// Bytes requested, or 0 for `free()`.
+    pub size: usize,
+    /// Did the injector fail this call?
+    pub failed: bool,
+}
+
+/// Makes allocations on the current thread fail on purpose, for testing handling of `Error`.
+///
+/// Configure it, then `install()` it. Every allocator call on the thread is recorded while it is installed.
+#[derive(Debug, Clone, Default)]
+pub struct Injector
+{
+    fail_nth: Option<usize>,
+    probability: f64,
+    seed: u64,
+    fail_above: Option<usize>,
+}
+
+/// An installed `Injector`. Uninstalls it when dropped.
+#[derive(Debug)]
+pub struct InjectorGuard
+{
+    _private: (),
+}
+
+#[derive(Debug)]
+struct State
+{
+    injector: Injector,
+    allocations: usize,
+    rng: u64,
+    calls: Vec<Call>,
+}
+
+thread_local! {
+    static STATE: RefCell<Option<State>> = const { RefCell::new(None) };
+}
+
+impl State
+{
+    /// xorshift64* step, as a float in `[0, 1)`.
+    fn next_f64(&mut self) -> f64
+    {
+	self.rng ^= self.rng >> 12;
+	self.rng ^= self.rng << 25;
+	self.rng ^= self.rng >> 27;
+	(self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn should_fail(&mut self, size: usize) -> bool
+    {
+	self.allocations += 1;
+	let nth = self.injector.fail_nth == Some(self.allocations);
+	let above = self.injector.fail_above.is_some_and(|x| size > x);
+	let random = self.injector.probability > 0.0 && self.next_f64() < self.injector.probability;
+	nth || above || random
+    }
+}
+
+impl Injector
+{
+    /// Create an injector that records calls but fails nothing.
+    pub fn new() -> Self
+    {
+	Self::default()
+    }
+
+    /// Fail the `n`th allocation after installing, counting from 1.
+    pub fn fail_nth(mut self, n: usize) -> Self
+    {
+	self.fail_nth = Some(n);
+	self
+    }
+
+    /// Fail each allocation with `probability`, using a random number generator seeded with `seed`.
+    pub fn fail_with_probability(mut self, probability: f64, seed: u64) -> Self
+    {
+	self.probability = probability;
+	self.seed = seed;
+	self
+    }
+
+    /// Fail every allocation of more than `bytes` bytes.
+    pub fn fail_above(mut self, bytes: usize) -> Self
+    {
+	self.fail_above = Some(bytes);
+	self
+    }
+
+    /// Install the injector on the current thread, replacing any already installed.
+    pub fn install(self) -> InjectorGuard
+    {
+	let rng = if self.seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { self.seed };
+	STATE.with(|state| *state.borrow_mut() = Some(State {
+	    injector: self,
+	    allocations: 0,
+	    rng,
+	    calls: Vec::new(),
+	}));
+	InjectorGuard{_private: ()}
+    }
+}
+
+impl InjectorGuard
+{
+    /// The calls recorded since installing.
+    pub fn calls(&self) -> Vec<Call>
+    {
+	STATE.with(|state| state.borrow().as_ref().map(|x| x.calls.clone()).unwrap_or_default())
+    }
+
+    /// The number of allocations (calls other than `free()`) since installing.
+    pub fn allocations(&self) -> usize
+    {
+	STATE.with(|state| state.borrow().as_ref().map_or(0, |x| x.allocations))
+    }
+
+    /// Forget the recorded calls.
+    pub fn clear(&self)
+    {
+	STATE.with(|state| if let Some(state) = state.borrow_mut().as_mut() {
+	    state.calls.clear();
+	});
+    }
+}
+
+impl Drop for InjectorGuard
+{
+    fn drop(&mut self)
+    {
+	STATE.with(|state| state.borrow_mut().take());
+    }
+}
+
+/// Record an allocator call, returning `true` if it should fail.
+pub(crate) fn intercept(kind: CallKind, size: usize) -> bool
+{
+    STATE.try_with(|state| {
+	let mut state = state.borrow_mut();
+	let state = match state.as_mut() {
+	    Some(state) => state,
+	    None => return false,
+	};
+	let failed = kind != CallKind::Free && state.should_fail(size);
+	state.calls.push(Call{kind, size, failed});
+	failed
+    }).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn fail_nth()
+    {
+	let guard = Injector::new().fail_nth(2).install();
+	assert!(HeapArray::<u32>::try_new(4).is_ok());
+	assert_eq!(HeapArray::<u32>::try_new_uninit(4).unwrap_err(), Error::Alloc);
+	assert!(HeapArray::<u32>::try_new(4).is_ok());
+	assert_eq!(guard.calls(), vec![
+	    Call{kind: CallKind::Calloc, size: 16, failed: false},
+	    Call{kind: CallKind::Free, size: 0, failed: false},
+	    Call{kind: CallKind::Malloc, size: 16, failed: true},
+	    Call{kind: CallKind::Calloc, size: 16, failed: false},
+	    Call{kind: CallKind::Free, size: 0, failed: false},
+	]);
+    }
+
+    #[test]
+    fn fail_above()
+    {
+	let _guard = Injector::new().fail_above(64).install();
+	let mut array = HeapArray::<u8>::try_new(64).unwrap();
+	assert!(array.try_resize(65).is_err());
+	assert_eq!(array.len(), 64);
+    }
+
+    #[test]
+    fn probability()
+    {
+	let run = || {
+	    let guard = Injector::new().fail_with_probability(0.5, 42).install();
+	    let results: Vec<_> = (0..32).map(|_| HeapArray::<u8>::try_new(1).is_ok()).collect();
+	    assert_eq!(guard.allocations(), 32);
+	    results
+	};
+	let results = run();
+	assert!(results.contains(&true) && results.contains(&false));
+	assert_eq!(results, run());
+    }
+}
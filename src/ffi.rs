@@ -0,0 +1,211 @@
+use super::*;
+use pod::Pod;
+use std::{
+    ffi::c_void,
+    os::raw::c_int,
+};
+
+/// An array shared with C as `malloc_array_t` (see `include/malloc_array.h`).
+///
+/// The memory is allocated by this crate's allocator backend and must be freed with `malloc_array_free()`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct MallocArray
+{
+    /// Pointer to the elements. `NULL` when empty.
+    pub data: *mut c_void,
+    /// Number of elements.
+    pub len: usize,
+    /// Size in bytes of each element.
+    pub element_size: usize,
+}
+
+impl MallocArray
+{
+    /// An empty array of elements of `element_size` bytes.
+    pub const fn empty(element_size: usize) -> Self
+    {
+	Self {
+	    data: ptr::NULL_PTR as *mut c_void,
+	    len: 0,
+	    element_size,
+	}
+    }
+
+    /// Consumes the descriptor, taking ownership of the memory as a `HeapArray<T>`.
+    ///
+    /// # Safety
+    /// The descriptor must have been created by this crate and own its memory.
+    ///
+    /// # Errors
+    /// Returns the descriptor back if its element size is not that of `T`.
+    pub unsafe fn into_heap_array<T: Pod>(self) -> Result<HeapArray<T>, Self>
+    {
+	if self.element_size != std::mem::size_of::<T>() {
+	    return Err(self);
+	}
+	Ok(HeapArray::from_raw_parts(self.data as *mut T, self.len))
+    }
+}
+
+impl<T: Pod> HeapArray<T>
+{
+    /// Consumes the instance, handing ownership of the memory to C as a `MallocArray` descriptor.
+    pub fn into_ffi(self) -> MallocArray
+    {
+	let (data, len) = self.into_raw_parts();
+	MallocArray {
+	    data: data as *mut c_void,
+	    len,
+	    element_size: std::mem::size_of::<T>(),
+	}
+    }
+}
+
+unsafe fn create(element_size: usize, len: usize, zeroed: bool, out: *mut MallocArray) -> c_int
+{
+    if out.is_null() {
+	return -1;
+    }
+    let data = match len.checked_mul(element_size) {
+	Some(_) if zeroed => alloc::calloc(len, element_size),
+	Some(bytes) => alloc::malloc(bytes),
+	None => Err(Error::Alloc),
+    };
+    match data {
+	Ok(data) => {
+	    out.write(MallocArray {
+		data: data as *mut c_void,
+		len,
+		element_size,
+	    });
+	    0
+	},
+	Err(_) => -1,
+    }
+}
+
+/// Allocate an uninitialised array of `len` elements of `element_size` bytes into `*out`. Returns 0 on success, -1 on failure.
+///
+/// # Safety
+/// `out` must be `NULL` or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn malloc_array_create(element_size: usize, len: usize, out: *mut MallocArray) -> c_int
+{
+    create(element_size, len, false, out)
+}
+
+/// Allocate a zeroed array of `len` elements of `element_size` bytes into `*out`. Returns 0 on success, -1 on failure.
+///
+/// # Safety
+/// `out` must be `NULL` or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn malloc_array_create_zeroed(element_size: usize, len: usize, out: *mut MallocArray) -> c_int
+{
+    create(element_size, len, true, out)
+}
+
+/// Resize `*array` to `len` elements with `realloc()`. Returns 0 on success, or -1 on failure leaving the array unchanged.
+///
+/// # Safety
+/// `array` must be `NULL` or point to a descriptor owning its memory.
+#[no_mangle]
+pub unsafe extern "C" fn malloc_array_resize(array: *mut MallocArray, len: usize) -> c_int
+{
+    let array = match array.as_mut() {
+	Some(array) => array,
+	None => return -1,
+    };
+    let bytes = match len.checked_mul(array.element_size) {
+	Some(bytes) => bytes,
+	None => return -1,
+    };
+    match alloc::realloc(array.data as VoidPointer, bytes) {
+	Ok(data) => {
+	    array.data = data as *mut c_void;
+	    array.len = len;
+	    0
+	},
+	Err(_) => -1,
+    }
+}
+
+/// Number of elements in `*array`, or 0 if `array` is `NULL`.
+///
+/// # Safety
+/// `array` must be `NULL` or point to a descriptor.
+#[no_mangle]
+pub unsafe extern "C" fn malloc_array_len(array: *const MallocArray) -> usize
+{
+    array.as_ref().map_or(0, |x| x.len)
+}
+
+/// Pointer to the elements of `*array`, or `NULL` if it is empty or `array` is `NULL`.
+///
+/// # Safety
+/// `array` must be `NULL` or point to a descriptor.
+#[no_mangle]
+pub unsafe extern "C" fn malloc_array_data(array: *const MallocArray) -> *mut c_void
+{
+    array.as_ref().map_or(ptr::NULL_PTR as *mut c_void, |x| x.data)
+}
+
+/// Free the memory of `*array` and leave it empty. Does nothing if `array` is `NULL`.
+///
+/// # Safety
+/// `array` must be `NULL` or point to a descriptor owning its memory.
+#[no_mangle]
+pub unsafe extern "C" fn malloc_array_free(array: *mut MallocArray)
+{
+    if let Some(array) = array.as_mut() {
+	alloc::free(array.data as VoidPointer);
+	*array = MallocArray::empty(array.element_size);
+    }
+}
+
+/// Take the memory out of `*array`, leaving it empty. The length is written to `*len` if it is not `NULL`.
+///
+/// The memory must be given back with `malloc_array_from_raw()` to be freed.
+///
+/// # Safety
+/// `array` and `len` must each be `NULL` or valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn malloc_array_into_raw(array: *mut MallocArray, len: *mut usize) -> *mut c_void
+{
+    let array = match array.as_mut() {
+	Some(array) => array,
+	None => return ptr::NULL_PTR as *mut c_void,
+    };
+    if let Some(len) = len.as_mut() {
+	*len = array.len;
+    }
+    let data = array.data;
+    *array = MallocArray::empty(array.element_size);
+    data
+}
+
+/// Create a descriptor for memory taken with `malloc_array_into_raw()`.
+#[no_mangle]
+pub extern "C" fn malloc_array_from_raw(data: *mut c_void, element_size: usize, len: usize) -> MallocArray
+{
+    MallocArray {
+	data,
+	len,
+	element_size,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn element_size()
+    {
+	let array = heap![1u16, 2].into_ffi();
+	assert_eq!(array.element_size, 2);
+	let array = unsafe { array.into_heap_array::<u8>() }.unwrap_err();
+	assert_eq!(&unsafe { array.into_heap_array::<u16>() }.unwrap()[..], &[1, 2]);
+    }
+}
@@ -0,0 +1,134 @@
+use super::*;
+use std::ffi::c_void;
+
+/// Releases memory that did not come from `alloc::malloc()`.
+pub(crate) type Deleter = Box<dyn FnOnce(VoidPointer) + Send + Sync>;
+
+/// Free `ptr` with `deleter`, or with `free()` if there is none.
+pub(crate) unsafe fn release(ptr: VoidPointer, deleter: Option<Deleter>)
+{
+    match deleter {
+	Some(deleter) => deleter(ptr),
+	None => alloc::free(ptr),
+    }
+}
+
+impl<T: 'static> HeapArray<T>
+{
+    /// Create a `HeapArray<T>` from memory owned by another allocator. `deleter` is called with the pointer and number of elements to release it.
+    ///
+    /// Operations that need `malloc()`ed memory, such as `resize()` and `into_raw_parts()`, copy the elements out first.
+    ///
+    /// # Safety
+    /// `ptr` must point to `size` initialised elements that `deleter` can release.
+    pub unsafe fn from_foreign<F>(ptr: *mut T, size: usize, deleter: F) -> Self
+    where F: FnOnce(*mut T, usize) + Send + Sync + 'static
+    {
+	let mut output = Self::from_raw_parts(ptr, size);
+	output.deleter = Some(Box::new(move |ptr| deleter(ptr as *mut T, size)));
+	output
+    }
+
+    /// Create a `HeapArray<T>` from memory released with a C function such as `g_free()` or `sqlite3_free()`.
+    ///
+    /// # Safety
+    /// `ptr` must point to `size` initialised elements that `free` can release.
+    pub unsafe fn from_foreign_c(ptr: *mut T, size: usize, free: unsafe extern "C" fn(*mut c_void)) -> Self
+    {
+	Self::from_foreign(ptr, size, move |ptr, _| free(ptr as *mut c_void))
+    }
+}
+
+impl<T> HeapArray<T>
+{
+    /// Was this instance created with `from_foreign()`, and still owns memory from another allocator?
+    pub fn is_foreign(&self) -> bool
+    {
+	self.deleter.is_some()
+    }
+
+    /// Move the elements of a foreign array into `malloc()`ed memory and release the foreign memory.
+    pub(crate) fn make_owned(&mut self) -> Result<(), Error>
+    {
+	if let Some(deleter) = self.deleter.take() {
+	    unsafe {
+		let ptr = match alloc::malloc(self.len_bytes()) {
+		    Ok(ptr) => ptr,
+		    Err(err) => {
+			self.deleter = Some(deleter);
+			return Err(err);
+		    },
+		};
+		if self.len_bytes() > 0 {
+		    ptr::memcpy(ptr, self.ptr as ConstVoidPointer, self.len_bytes());
+		}
+		if self.ptr != ptr::null() {
+		    deleter(self.ptr as VoidPointer);
+		}
+		self.ptr = ptr as *mut T;
+	    }
+	}
+	Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::sync::{
+	Arc,
+	atomic::{
+	    AtomicUsize,
+	    Ordering,
+	},
+    };
+
+    fn foreign(values: &[u32], freed: &Arc<AtomicUsize>) -> HeapArray<u32>
+    {
+	let boxed: Box<[u32]> = values.into();
+	let size = boxed.len();
+	let freed = Arc::clone(freed);
+	unsafe {
+	    HeapArray::from_foreign(Box::into_raw(boxed) as *mut u32, size, move |ptr, size| {
+		drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, size)));
+		freed.fetch_add(1, Ordering::SeqCst);
+	    })
+	}
+    }
+
+    #[test]
+    fn deleter()
+    {
+	let freed = Arc::new(AtomicUsize::new(0));
+	let array = foreign(&[1, 2, 3], &freed);
+	assert!(array.is_foreign());
+	assert_eq!(&array[..], &[1, 2, 3]);
+	drop(array);
+	assert_eq!(freed.load(Ordering::SeqCst), 1);
+
+	let sum: u32 = foreign(&[1, 2, 3], &freed).into_iter().sum();
+	assert_eq!(sum, 6);
+	assert_eq!(freed.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn copies()
+    {
+	let freed = Arc::new(AtomicUsize::new(0));
+	let array = foreign(&[1, 2, 3], &freed).resize(4);
+	assert_eq!(freed.load(Ordering::SeqCst), 1);
+	assert!(!array.is_foreign());
+	assert_eq!(&array[..3], &[1, 2, 3]);
+
+	let (ptr, size) = foreign(&[4, 5], &freed).into_raw_parts();
+	assert_eq!(freed.load(Ordering::SeqCst), 2);
+	assert_eq!(&unsafe { HeapArray::from_raw_parts(ptr, size) }[..], &[4, 5]);
+
+	let frozen = foreign(&[6], &freed).freeze();
+	assert_eq!((&frozen[..], freed.load(Ordering::SeqCst)), (&[6][..], 3));
+
+	let string = unsafe { HeapArray::from_foreign_c(libc::strdup(b"abc\0".as_ptr() as *const _) as *mut u8, 3, libc::free) };
+	assert_eq!(&string[..], b"abc");
+    }
+}
@@ -0,0 +1,274 @@
+use super::*;
+use pod::Pod;
+
+/// A read-only array in page-aligned memory protected with `mprotect(PROT_READ)`. Created with `HeapArray::freeze()`, or `freeze_unchecked()` for types that are not `Pod`.
+///
+/// Any attempt to write to the memory (even through raw pointers) faults.
+pub struct FrozenHeapArray<T>
+{
+    ptr: *mut T,
+    size: usize,
+    drop_check: bool,
+}
+
+unsafe impl<T> Sync for FrozenHeapArray<T>
+where T: Sync{}
+unsafe impl<T> Send for FrozenHeapArray<T>
+where T: Send{}
+
+/// Round `bytes` up to a whole number of pages, if that fits in `usize`.
+fn page_round(bytes: usize) -> Option<usize>
+{
+    bytes.checked_next_multiple_of(alloc::page_size())
+}
+
+/// Set the protection of the pages spanning `bytes` bytes from `ptr`.
+unsafe fn protect(ptr: VoidPointer, bytes: usize, prot: libc::c_int)
+{
+    if bytes > 0 && libc::mprotect(ptr as *mut libc::c_void, page_round(bytes).expect("page_round()"), prot) != 0 {
+	panic!("mprotect(): {}", std::io::Error::last_os_error());
+    }
+}
+
+impl<T> HeapArray<T>
+{
+    /// Creates a new `HeapArray<T>` from zeroed memory that starts on a page boundary and owns every page it spans.
+    ///
+    /// Arrays created this way can be `freeze()`d without copying.
+    pub fn new_page_aligned(size: usize) -> Self
+    {
+	Self::try_new_page_aligned(size).expect("posix_memalign()")
+    }
+
+    /// Creates a new `HeapArray<T>` from zeroed memory that starts on a page boundary and owns every page it spans, or an error if allocation fails.
+    pub fn try_new_page_aligned(size: usize) -> Result<Self, Error>
+    {
+	let bytes = page_round(Self::bytes_for(size)?).ok_or(Error::Alloc)?;
+	unsafe {
+	    let ptr = alloc::aligned_malloc(alloc::page_size(), bytes)?;
+	    if bytes > 0 {
+		ptr::memset(ptr as *mut u8, 0, bytes);
+	    }
+	    Ok(Self::from_raw_parts(ptr as *mut T, size))
+	}
+    }
+
+    /// Does this instance start on a page boundary and own every page it spans?
+    fn owns_pages(&self) -> bool
+    {
+	let bytes = page_round(self.len_bytes()).expect("page_round()");
+	!self.is_foreign()
+	    && (self.ptr as usize).is_multiple_of(alloc::page_size())
+	    && unsafe { alloc::usable_size(self.ptr as VoidPointer) } >= bytes
+    }
+
+    /// Consumes the instance, making the memory read-only with `mprotect()`.
+    ///
+    /// The elements are moved into page-aligned memory first, unless the array was created with `new_page_aligned()`.
+    pub fn freeze(self) -> FrozenHeapArray<T>
+    where T: Pod
+    {
+	unsafe { self.freeze_unchecked() }
+    }
+
+    /// Consumes the instance, making the memory read-only with `mprotect()`, for any `T`.
+    ///
+    /// # Safety
+    /// `T` must have no interior mutability (`Cell`, atomics, `Mutex`, ...) stored inline in the elements: writing through it to the frozen pages faults.
+    pub unsafe fn freeze_unchecked(self) -> FrozenHeapArray<T>
+    {
+	let drop_check = self.drop_check;
+	let bytes = self.len_bytes();
+	let (ptr, size) = if bytes == 0 || self.owns_pages() {
+	    self.into_raw_parts()
+	} else {
+	    unsafe {
+		let ptr = alloc::aligned_malloc(alloc::page_size(), page_round(bytes).expect("posix_memalign()")).expect("posix_memalign()");
+		ptr::memcpy(ptr, self.ptr as ConstVoidPointer, bytes);
+		let size = self.len();
+		self.free();
+		(ptr as *mut T, size)
+	    }
+	};
+	unsafe {
+	    protect(ptr as VoidPointer, bytes, libc::PROT_READ);
+	}
+	FrozenHeapArray {
+	    ptr,
+	    size,
+	    drop_check,
+	}
+    }
+}
+
+impl<T> FrozenHeapArray<T>
+{
+    /// Number of elements in this instance.
+    pub fn len(&self) -> usize
+    {
+	self.size
+    }
+
+    /// Is this instance empty?
+    pub fn is_empty(&self) -> bool
+    {
+	self.size == 0
+    }
+
+    /// Size of memory of this instance in bytes.
+    pub fn len_bytes(&self) -> usize
+    {
+	std::mem::size_of::<T>() * self.size
+    }
+
+    /// As an immutable slice of `T`.
+    pub fn as_slice(&self) -> &[T]
+    {
+	let ptr = if self.ptr == ptr::null() {
+	    std::ptr::NonNull::dangling().as_ptr()
+	} else {
+	    self.ptr
+	};
+	unsafe{slice::from_raw_parts(ptr, self.size)}
+    }
+
+    /// As immutable raw pointer.
+    pub fn as_ptr(&self) -> *const T
+    {
+	self.ptr as *const T
+    }
+
+    /// An immutable slice of the memory.
+    pub fn memory(&self) -> &[u8]
+    {
+	if self.ptr == ptr::null() {
+	    &[]
+	} else {
+	    unsafe{slice::from_raw_parts(self.ptr as *const u8, self.len_bytes())}
+	}
+    }
+
+    /// Immutable slice iterator for this instance
+    pub fn iter(&self) -> slice::Iter<'_, T>
+    {
+	self.as_slice().iter()
+    }
+
+    /// Consumes the instance, restoring write access to the memory.
+    pub fn thaw(self) -> HeapArray<T>
+    {
+	unsafe {
+	    protect(self.ptr as VoidPointer, self.len_bytes(), libc::PROT_READ | libc::PROT_WRITE);
+	    let mut output = HeapArray::from_raw_parts(self.ptr, self.size);
+	    output.drop_check = self.drop_check;
+	    std::mem::forget(self);
+	    output
+	}
+    }
+}
+
+impl<T> Drop for FrozenHeapArray<T>
+{
+    fn drop(&mut self)
+    {
+	unsafe {
+	    protect(self.ptr as VoidPointer, self.len_bytes(), libc::PROT_READ | libc::PROT_WRITE);
+	    let mut array = HeapArray::from_raw_parts(self.ptr, self.size);
+	    array.drop_check = self.drop_check;
+	    drop(array);
+	}
+    }
+}
+
+impl<T> Deref for FrozenHeapArray<T>
+{
+    type Target = [T];
+    fn deref(&self) -> &Self::Target
+    {
+	self.as_slice()
+    }
+}
+
+impl<T> AsRef<[T]> for FrozenHeapArray<T>
+{
+    fn as_ref(&self) -> &[T]
+    {
+	self.as_slice()
+    }
+}
+
+impl<T> Borrow<[T]> for FrozenHeapArray<T>
+{
+    fn borrow(&self) -> &[T]
+    {
+	self.as_slice()
+    }
+}
+
+impl<T, I> Index<I> for FrozenHeapArray<T>
+where I: SliceIndex<[T]>
+{
+    type Output = <I as SliceIndex<[T]>>::Output;
+    fn index(&self, index: I) -> &Self::Output
+    {
+	&self.as_slice()[index]
+    }
+}
+
+impl<T: Pod> From<HeapArray<T>> for FrozenHeapArray<T>
+{
+    fn from(from: HeapArray<T>) -> Self
+    {
+	from.freeze()
+    }
+}
+
+impl<T> From<FrozenHeapArray<T>> for HeapArray<T>
+{
+    fn from(from: FrozenHeapArray<T>) -> Self
+    {
+	from.thaw()
+    }
+}
+
+impl<T> fmt::Debug for FrozenHeapArray<T>
+where T: fmt::Debug
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	write!(f, "{}: {:?}", std::any::type_name::<Self>(), self.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn freeze_thaw()
+    {
+	let frozen = unsafe { heap!["one".to_owned(), "two".to_owned()].freeze_unchecked() };
+	assert_eq!(frozen.as_ptr() as usize % alloc::page_size(), 0);
+	assert_eq!(&frozen[..], &["one", "two"]);
+
+	let mut thawed = frozen.thaw();
+	thawed[0].push('!');
+	assert_eq!(&thawed[..], &["one!", "two"]);
+    }
+
+    #[test]
+    fn no_copy()
+    {
+	let mut array = HeapArray::<u64>::new_page_aligned(1000);
+	array[999] = 5;
+	let ptr = array.as_ptr();
+	let frozen = array.freeze();
+	assert_eq!(frozen.as_ptr(), ptr);
+	assert_eq!(frozen[999], 5);
+
+	let empty: HeapArray<u8> = heap![];
+	assert!(empty.freeze().is_empty());
+	assert_eq!(HeapArray::<u64>::try_new_page_aligned(usize::MAX / 4).unwrap_err(), Error::Alloc);
+    }
+}
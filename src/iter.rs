@@ -21,6 +21,8 @@ pub struct IntoIter<T>
     start: *mut T,
     current_offset: usize,
     sz: usize,
+    charge: Option<budget::Charge>,
+    deleter: Option<foreign::Deleter>,
 }
 
 unsafe impl<T: Send> Send for IntoIter<T>{}
@@ -30,6 +32,10 @@ impl<T> IntoIter<T>
 {
     fn current(&mut self) -> *mut T
     {
+	if self.start == ptr::null() {
+	    // Zero-sized elements from `zst_noalloc` have no allocation.
+	    return std::ptr::NonNull::dangling().as_ptr();
+	}
 	unsafe {
 	    self.start.offset(self.current_offset as isize)
 	}
@@ -38,9 +44,10 @@ impl<T> IntoIter<T>
     {
 	if self.start != ptr::null() && self.current_offset >= self.sz {
 	    unsafe {
-		alloc::free(self.start as VoidPointer);
+		foreign::release(self.start as VoidPointer, self.deleter.take());
 	    }
 	    self.start = ptr::null();
+	    self.charge = None;
 	}
     }
     fn drain_if_needed(&mut self)
@@ -55,9 +62,10 @@ impl<T> IntoIter<T>
 		    }
 		}
 
-		alloc::free(self.start as VoidPointer);
+		foreign::release(self.start as VoidPointer, self.deleter.take());
 	    }
 	    self.start = ptr::null();
+	    self.charge = None;
 	}
     }
 }
@@ -103,12 +111,14 @@ impl<T> IntoIterator for HeapArray<T>
     type Item = T;
     type IntoIter = IntoIter<T>;
 
-    fn into_iter(self) -> Self::IntoIter
+    fn into_iter(mut self) -> Self::IntoIter
     {
 	let output = Self::IntoIter {
 	    start: self.ptr,
 	    current_offset: 0,
 	    sz: self.len(),
+	    charge: self.charge.take(),
+	    deleter: self.deleter.take(),
 	};
 	forget(self);
 	output
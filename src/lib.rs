@@ -191,6 +191,53 @@ mod reinterpret;
 pub mod init;
 pub use init::InitIterExt;
 pub mod store;
+pub mod cursor;
+pub use cursor::HeapCursor;
+pub mod vectored;
+pub mod pod;
+pub use pod::Pod;
+mod serial;
+pub mod mapped;
+pub use mapped::MappedArray;
+pub mod shared;
+pub use shared::SharedHeapArray;
+pub mod frozen;
+pub use frozen::FrozenHeapArray;
+pub mod budget;
+pub use budget::Budget;
+pub use alloc::Error;
+pub mod arena;
+pub use arena::Arena;
+pub mod pool;
+pub use pool::BufferPool;
+mod foreign;
+pub mod view;
+pub use view::{
+    HeapSlice,
+    HeapSliceMut,
+};
+pub mod cstring;
+pub use cstring::HeapCString;
+pub mod string;
+pub use string::HeapString;
+pub mod ptrarray;
+pub use ptrarray::HeapPtrArray;
+pub mod sentinel;
+pub mod thin;
+pub use thin::ThinHeapArray;
+pub mod boxed;
+pub use boxed::HeapBox;
+pub mod rc;
+pub use rc::{
+    HeapRc,
+    HeapArc,
+};
+#[cfg(feature="fault_inject")]
+pub mod fault;
+#[cfg(feature="trace")]
+pub mod trace;
+#[cfg(feature="ffi")]
+pub mod ffi;
 
 use std::{
     ops::{
@@ -209,6 +256,7 @@ use std::{
 	Send,
 	Sync,
     },
+    sync::Arc,
 };
 use crate::{
     ptr::{
@@ -303,6 +351,9 @@ pub struct HeapArray<T> {
 
     /// Call `drop()` on sub-elements when `drop`ping the array. This is not needed for types that implement `Copy`.
     pub drop_check: bool,
+
+    charge: Option<budget::Charge>,
+    deleter: Option<foreign::Deleter>,
 }
 
 unsafe impl<T> Sync for HeapArray<T>
@@ -345,39 +396,107 @@ impl<T> HeapArray<T>
 	ptr::memset(self.ptr as *mut u8, value, self.len_bytes());
     }
 
+    /// Size in bytes of `size` elements.
+    fn bytes_for(size: usize) -> Result<usize, Error>
+    {
+	size.checked_mul(Self::element_size()).ok_or(Error::Alloc)
+    }
+
     /// Creates a new `HeapArray<T>` from zeroed memory.
     pub fn new(size: usize) -> Self
     {
-	Self {
-	    ptr: unsafe{alloc::calloc(size, Self::element_size()).expect("calloc()")} as *mut T,
-	    size,
-	    drop_check: true,
+	Self::try_new(size).expect("calloc()")
+    }
+
+    /// Creates a new `HeapArray<T>` from zeroed memory, or an error if allocation fails.
+    pub fn try_new(size: usize) -> Result<Self, Error>
+    {
+	Self::bytes_for(size)?;
+	unsafe {
+	    Ok(Self::from_raw_parts(alloc::calloc(size, Self::element_size())? as *mut T, size))
 	}
     }
 
+    /// Creates a new `HeapArray<T>` from zeroed memory charged against `budget`.
+    pub fn try_new_in(size: usize, budget: &Arc<Budget>) -> Result<Self, Error>
+    {
+	let charge = budget::Charge::new(budget, Self::bytes_for(size)?)?;
+	let mut output = Self::try_new(size)?;
+	output.charge = Some(charge);
+	Ok(output)
+    }
+
     /// Creates a new `HeapArray<T>` from uninitialised memory.
     pub fn new_uninit(size: usize) -> Self
     {
-	Self {
-	    ptr: unsafe{alloc::malloc(size * Self::element_size()).expect("malloc()")} as *mut T,
-	    size,
-	    drop_check: true,
-	}
+	Self::try_new_uninit(size).expect("malloc()")
     }
 
-    /// Consumes the instance, returning a new instance after calling `realloc()` on the underlying memory.
-    pub fn resize(self, size: usize) -> Self
+    /// Creates a new `HeapArray<T>` from uninitialised memory, or an error if allocation fails.
+    pub fn try_new_uninit(size: usize) -> Result<Self, Error>
     {
 	unsafe {
-	    let ptr = alloc::realloc(self.ptr as VoidPointer, size).expect("realloc()") as *mut T;
-	    
-	    let output = Self {
-		ptr,
-		size,
-		drop_check: self.drop_check
-	    };
-	    std::mem::forget(self);
-	    output
+	    Ok(Self::from_raw_parts(alloc::malloc(Self::bytes_for(size)?)? as *mut T, size))
+	}
+    }
+
+    /// Creates a new `HeapArray<T>` from uninitialised memory charged against `budget`.
+    pub fn try_new_uninit_in(size: usize, budget: &Arc<Budget>) -> Result<Self, Error>
+    {
+	let charge = budget::Charge::new(budget, Self::bytes_for(size)?)?;
+	let mut output = Self::try_new_uninit(size)?;
+	output.charge = Some(charge);
+	Ok(output)
+    }
+
+    /// The budget this instance is charged against, if any.
+    pub fn budget(&self) -> Option<&Arc<Budget>>
+    {
+	self.charge.as_ref().map(|x| x.budget())
+    }
+
+    /// Uninitialised memory of the same length, charged against the same budget.
+    fn new_uninit_like(&self) -> Self
+    {
+	let mut output = match self.budget() {
+	    Some(budget) => Self::try_new_uninit_in(self.len(), budget).expect("malloc()"),
+	    None => Self::new_uninit(self.len()),
+	};
+	output.drop_check = self.drop_check;
+	output
+    }
+
+    /// Consumes the instance, returning a new instance after calling `realloc()` on the underlying memory.
+    pub fn resize(mut self, size: usize) -> Self
+    {
+	self.try_resize(size).expect("realloc()");
+	self
+    }
+
+    /// Change the number of elements by calling `realloc()` on the underlying memory.
+    ///
+    /// # Errors
+    /// If allocation fails or the new size exceeds the budget. The instance is unchanged.
+    pub fn try_resize(&mut self, size: usize) -> Result<(), Error>
+    {
+	let bytes = Self::bytes_for(size)?;
+	let old_bytes = self.len_bytes();
+	self.make_owned()?;
+	if let Some(charge) = &mut self.charge {
+	    charge.resize(bytes)?;
+	}
+	match unsafe { alloc::realloc(self.ptr as VoidPointer, bytes) } {
+	    Ok(ptr) => {
+		self.ptr = ptr as *mut T;
+		self.size = size;
+		Ok(())
+	    },
+	    Err(err) => {
+		if let Some(charge) = &mut self.charge {
+		    charge.resize(old_bytes).ok();
+		}
+		Err(err)
+	    },
 	}
     }
 
@@ -425,16 +544,26 @@ impl<T> HeapArray<T>
 	}
     }
 
+    /// Pointer suitable for creating slices. `NULL` (from `zst_noalloc`) is replaced with a dangling pointer.
+    fn slice_ptr(&self) -> *mut T
+    {
+	if self.ptr == ptr::null() {
+	    std::ptr::NonNull::dangling().as_ptr()
+	} else {
+	    self.ptr
+	}
+    }
+
     /// As an immutable slice of `T`.
     pub fn as_slice(&self) -> &[T]
     {
-	unsafe{slice::from_raw_parts(self.ptr, self.size)}
+	unsafe{slice::from_raw_parts(self.slice_ptr(), self.size)}
     }
 
     /// As a mutable slice of `T`.
     pub fn as_slice_mut(&mut self) -> &mut [T]
     {
-	unsafe{slice::from_raw_parts_mut(self.ptr, self.size)}
+	unsafe{slice::from_raw_parts_mut(self.slice_ptr(), self.size)}
     }
 
     /// As immutable raw pointer.
@@ -453,20 +582,24 @@ impl<T> HeapArray<T>
     pub fn memory(&self) -> &[u8]
     {
 	unsafe {
-	    slice::from_raw_parts(self.ptr as *const u8, self.len_bytes())
+	    slice::from_raw_parts(self.slice_ptr() as *const u8, self.len_bytes())
 	}
     }
 
     /// A mutable slice of the memory.
     pub unsafe fn memory_mut(&mut self) -> &mut [u8]
     {
-	slice::from_raw_parts_mut(self.ptr as *mut u8, self.len_bytes())
+	slice::from_raw_parts_mut(self.slice_ptr() as *mut u8, self.len_bytes())
     }
 
 
     /// Consumes the instance. Returns a raw pointer and the number of elements.
-    pub fn into_raw_parts(self) -> (*mut T, usize)
+    ///
+    /// The memory is credited back to the instance's budget, if any. Foreign arrays are copied into `malloc()`ed memory first.
+    pub fn into_raw_parts(mut self) -> (*mut T, usize)
     {
+	self.make_owned().expect("malloc()");
+	self.charge = None;
 	let op = (self.ptr, self.size);
 	std::mem::forget(self);
 	op
@@ -479,15 +612,18 @@ impl<T> HeapArray<T>
 	    ptr,
 	    size,
 	    drop_check: true,
+	    charge: None,
+	    deleter: None,
 	}
     }
 
     /// Consumes the instance. Frees the memory without dropping the items.
-    pub fn free(self)
+    pub fn free(mut self)
     {
+	self.charge = None;
 	if self.ptr != ptr::null() {
 	    unsafe {
-		alloc::free(self.ptr as VoidPointer);
+		foreign::release(self.ptr as VoidPointer, self.deleter.take());
 	    }
 	}
 	std::mem::forget(self);
@@ -527,6 +663,8 @@ impl<T> HeapArray<T>
     {
 	#[cfg(feature="assume_libc")]
 	unsafe {
+	    self.make_owned().expect("malloc()");
+	    self.charge = None;
 	    let bx = Box::from_raw(self.as_slice_mut() as *mut [T]);
 	    std::mem::forget(self);
 	    bx
@@ -543,13 +681,15 @@ impl<T> HeapArray<T>
     /// Reinterpret the memory of this instance into an insteance of a different type
     /// # Panics
     /// If `U` cannot fit into `T`.  
-    pub unsafe fn reinterpret<U>(self) -> HeapArray<U>
+    pub unsafe fn reinterpret<U>(mut self) -> HeapArray<U>
     {
 	assert!(self.len_bytes() % std::mem::size_of::<U>() == 0);
 	let output = HeapArray {
 	    size: self.len_bytes() / std::mem::size_of::<U>(),
 	    ptr: self.ptr as *mut U,
 	    drop_check: self.drop_check,
+	    charge: self.charge.take(),
+	    deleter: self.deleter.take(),
 	};
 	std::mem::forget(self);
 	output
@@ -600,8 +740,7 @@ impl<T> HeapArray<T>
     /// Clone the memory to a new instance.
     pub unsafe fn clone_mem(&self) -> Self
     {
-	let mut output = Self::new_uninit(self.len());
-	output.drop_check = self.drop_check;
+	let output = self.new_uninit_like();
 	ptr::memcpy(output.ptr as VoidPointer, self.ptr as VoidPointer, self.len_bytes());
 
 	output
@@ -610,6 +749,7 @@ impl<T> HeapArray<T>
     /// Leak the memory to a static slice reference.
     pub fn leak(mut self) -> &'static mut [T]
     {
+	self.charge = None;
 	unsafe {
 	    let bx = Box::from_raw(self.as_slice_mut() as *mut [T]);
 	    std::mem::forget(self);
@@ -718,7 +858,7 @@ impl<T> Drop for HeapArray<T>
 		    }
 		}
 	    }
-	    unsafe{alloc::free(self.ptr as VoidPointer)};
+	    unsafe{foreign::release(self.ptr as VoidPointer, self.deleter.take())};
 	    self.ptr = ptr::null::<T>();
 	}
     }
@@ -851,8 +991,7 @@ where T: Clone
     fn clone(&self) -> Self
     where T: Clone
     {
-	let mut output = Self::new_uninit(self.len());
-	output.drop_check = self.drop_check;
+	let mut output = self.new_uninit_like();
 
 	unsafe {
 	    for (i,x) in (0..self.len()).zip(self.iter())
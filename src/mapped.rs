@@ -0,0 +1,378 @@
+use super::*;
+use pod::Pod;
+use serial::{
+    Header,
+    HEADER_SIZE,
+};
+use std::{
+    io,
+    fs::{
+	File,
+	OpenOptions,
+    },
+    path::Path,
+    os::unix::{
+	io::AsRawFd,
+	fs::FileExt,
+    },
+    marker::PhantomData,
+    ffi::c_void,
+};
+
+/// Array persisted in a file mapped with `mmap(MAP_SHARED)`.
+///
+/// The file layout is the same as `HeapArray::write_to()`, so a serialized array can be mapped directly without copying.
+pub struct MappedArray<T: Pod>
+{
+    file: File,
+    map: *mut c_void,
+    map_size: usize,
+    size: usize,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T: Pod + Send> Send for MappedArray<T>{}
+unsafe impl<T: Pod + Sync> Sync for MappedArray<T>{}
+
+fn map_size_for<T>(len: usize) -> io::Result<usize>
+{
+    if std::mem::align_of::<T>() > HEADER_SIZE {
+	return Err(io::Error::new(io::ErrorKind::InvalidInput, "element alignment is larger than the header"));
+    }
+    std::mem::size_of::<T>().checked_mul(len)
+	.and_then(|x| x.checked_add(HEADER_SIZE))
+	.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "array too large to map"))
+}
+
+fn os_result(ret: libc::c_int) -> io::Result<()>
+{
+    if ret == -1 {
+	Err(io::Error::last_os_error())
+    } else {
+	Ok(())
+    }
+}
+
+impl<T: Pod> MappedArray<T>
+{
+    /// Map an existing file written by `HeapArray::write_to()` or `MappedArray::create()`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self>
+    {
+	let file = OpenOptions::new().read(true).write(true).open(path)?;
+	Self::from_file(file)
+    }
+
+    /// Create (or truncate) a file holding `len` zeroed elements and map it.
+    pub fn create<P: AsRef<Path>>(path: P, len: usize) -> io::Result<Self>
+    {
+	let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+	let map_size = map_size_for::<T>(len)?;
+	file.set_len(map_size as u64)?;
+
+	let mut this = Self::map(file, map_size, len)?;
+	this.write_header();
+	Ok(this)
+    }
+
+    /// Map the file at `path` if it exists, otherwise create it with `len` zeroed elements.
+    pub fn open_or_create<P: AsRef<Path>>(path: P, len: usize) -> io::Result<Self>
+    {
+	let path = path.as_ref();
+	match Self::open(path) {
+	    Err(err) if err.kind() == io::ErrorKind::NotFound => Self::create(path, len),
+	    other => other,
+	}
+    }
+
+    /// Map an already opened file. It must be opened for reading and writing.
+    pub fn from_file(file: File) -> io::Result<Self>
+    {
+	let file_size = file.metadata()?.len();
+	if file_size < HEADER_SIZE as u64 {
+	    return Err(io::Error::new(io::ErrorKind::InvalidData, "file too small for header"));
+	}
+	let mut bytes = [0u8; HEADER_SIZE];
+	file.read_exact_at(&mut bytes, 0)?;
+	let header = Header::from_bytes(&bytes);
+	header.validate::<T>()?;
+
+	let len = header.len()?;
+	let map_size = map_size_for::<T>(len)?;
+	if file_size < map_size as u64 {
+	    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file shorter than its header length"));
+	}
+	Self::map(file, map_size, len)
+    }
+
+    fn map(file: File, map_size: usize, size: usize) -> io::Result<Self>
+    {
+	let map = unsafe {
+	    libc::mmap(std::ptr::null_mut(), map_size, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED, file.as_raw_fd(), 0)
+	};
+	if map == libc::MAP_FAILED {
+	    return Err(io::Error::last_os_error());
+	}
+	Ok(Self {
+	    file,
+	    map,
+	    map_size,
+	    size,
+	    _marker: PhantomData,
+	})
+    }
+
+    fn write_header(&mut self)
+    {
+	let header = Header::new::<T>(self.size);
+	unsafe {
+	    ptr::memcpy(self.map as VoidPointer, header.as_bytes().as_ptr() as ConstVoidPointer, HEADER_SIZE);
+	}
+    }
+
+    /// Number of elements in this instance.
+    pub fn len(&self) -> usize
+    {
+	self.size
+    }
+
+    /// Is this instance empty?
+    pub fn is_empty(&self) -> bool
+    {
+	self.size == 0
+    }
+
+    /// Size of the element data in bytes.
+    pub fn len_bytes(&self) -> usize
+    {
+	self.size * std::mem::size_of::<T>()
+    }
+
+    /// As immutable raw pointer.
+    pub fn as_ptr(&self) -> *const T
+    {
+	unsafe {
+	    (self.map as *const u8).add(HEADER_SIZE) as *const T
+	}
+    }
+
+    /// As mutable raw pointer.
+    pub fn as_ptr_mut(&mut self) -> *mut T
+    {
+	unsafe {
+	    (self.map as *mut u8).add(HEADER_SIZE) as *mut T
+	}
+    }
+
+    /// As an immutable slice of `T`.
+    pub fn as_slice(&self) -> &[T]
+    {
+	unsafe{slice::from_raw_parts(self.as_ptr(), self.size)}
+    }
+
+    /// As a mutable slice of `T`.
+    pub fn as_slice_mut(&mut self) -> &mut [T]
+    {
+	unsafe{slice::from_raw_parts_mut(self.as_ptr_mut(), self.size)}
+    }
+
+    /// An immutable slice of the memory.
+    pub fn memory(&self) -> &[u8]
+    {
+	unsafe{slice::from_raw_parts(self.as_ptr() as *const u8, self.len_bytes())}
+    }
+
+    /// A mutable slice of the memory.
+    pub fn memory_mut(&mut self) -> &mut [u8]
+    {
+	unsafe{slice::from_raw_parts_mut(self.as_ptr_mut() as *mut u8, self.len_bytes())}
+    }
+
+    /// Immutable slice iterator for this instance
+    pub fn iter(&self) -> slice::Iter<'_, T>
+    {
+	self.as_slice().iter()
+    }
+
+    /// Mutable slice iterator for this instance
+    pub fn iter_mut(&mut self) -> slice::IterMut<'_, T>
+    {
+	self.as_slice_mut().iter_mut()
+    }
+
+    /// The underlying file.
+    pub fn file(&self) -> &File
+    {
+	&self.file
+    }
+
+    /// Consumes the instance, unmapping the memory and returning the file.
+    pub fn into_file(self) -> File
+    {
+	unsafe {
+	    libc::munmap(self.map, self.map_size);
+	    let file = std::ptr::read(&self.file);
+	    std::mem::forget(self);
+	    file
+	}
+    }
+
+    /// Synchronously write changes back to the file with `msync(MS_SYNC)`.
+    pub fn flush(&self) -> io::Result<()>
+    {
+	os_result(unsafe { libc::msync(self.map, self.map_size, libc::MS_SYNC) })
+    }
+
+    /// Schedule changes to be written back to the file with `msync(MS_ASYNC)`.
+    pub fn flush_async(&self) -> io::Result<()>
+    {
+	os_result(unsafe { libc::msync(self.map, self.map_size, libc::MS_ASYNC) })
+    }
+
+    /// Change the number of elements, growing or shrinking the file with `ftruncate()` and the mapping with `mremap()`.
+    ///
+    /// New elements are zeroed.
+    pub fn resize(&mut self, len: usize) -> io::Result<()>
+    {
+	let map_size = map_size_for::<T>(len)?;
+	if map_size > self.map_size {
+	    self.file.set_len(map_size as u64)?;
+	}
+	let map = unsafe {
+	    libc::mremap(self.map, self.map_size, map_size, libc::MREMAP_MAYMOVE)
+	};
+	if map == libc::MAP_FAILED {
+	    return Err(io::Error::last_os_error());
+	}
+	self.map = map;
+	self.map_size = map_size;
+	self.size = len;
+	self.write_header();
+	if (map_size as u64) < self.file.metadata()?.len() {
+	    self.file.set_len(map_size as u64)?;
+	}
+	Ok(())
+    }
+
+    /// Copy the elements into a new `HeapArray<T>`.
+    pub fn to_heap_array(&self) -> HeapArray<T>
+    {
+	unsafe {
+	    HeapArray::from_raw_copied(self.as_ptr(), self.size)
+	}
+    }
+}
+
+impl<T: Pod> Drop for MappedArray<T>
+{
+    fn drop(&mut self)
+    {
+	unsafe {
+	    libc::munmap(self.map, self.map_size);
+	}
+    }
+}
+
+impl<T: Pod> Deref for MappedArray<T>
+{
+    type Target = [T];
+    fn deref(&self) -> &Self::Target
+    {
+	self.as_slice()
+    }
+}
+impl<T: Pod> DerefMut for MappedArray<T>
+{
+    fn deref_mut(&mut self) -> &mut <Self as Deref>::Target
+    {
+	self.as_slice_mut()
+    }
+}
+
+impl<T: Pod> AsRef<[T]> for MappedArray<T>
+{
+    fn as_ref(&self) -> &[T]
+    {
+	self.as_slice()
+    }
+}
+impl<T: Pod> AsMut<[T]> for MappedArray<T>
+{
+    fn as_mut(&mut self) -> &mut [T]
+    {
+	self.as_slice_mut()
+    }
+}
+
+impl<T: Pod, I> Index<I> for MappedArray<T>
+where I: SliceIndex<[T]>
+{
+    type Output = <I as SliceIndex<[T]>>::Output;
+    fn index(&self, index: I) -> &Self::Output
+    {
+	&self.as_slice()[index]
+    }
+}
+impl<T: Pod, I> IndexMut<I> for MappedArray<T>
+where I: SliceIndex<[T]>
+{
+    fn index_mut(&mut self, index: I) -> &mut <Self as Index<I>>::Output
+    {
+	&mut self.as_slice_mut()[index]
+    }
+}
+
+impl<T: Pod + fmt::Debug> fmt::Debug for MappedArray<T>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf
+    {
+	std::env::temp_dir().join(format!("malloc-array-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn map_written()
+    {
+	let path = temp_path("map-written");
+	let heap = heap![1u32, 2, 3, 4];
+	heap.write_to(File::create(&path).unwrap()).unwrap();
+
+	let mapped = MappedArray::<u32>::open(&path).unwrap();
+	assert_eq!(&mapped[..], &heap[..]);
+	assert!(MappedArray::<u16>::open(&path).is_err());
+	std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn create_resize()
+    {
+	let path = temp_path("create-resize");
+	{
+	    let mut mapped = MappedArray::<u64>::create(&path, 2).unwrap();
+	    mapped[1] = 10;
+	    mapped.resize(1000).unwrap();
+	    mapped[999] = 20;
+	    mapped.flush().unwrap();
+	    assert_eq!(mapped[0], 0);
+	}
+	let mut mapped = MappedArray::<u64>::open_or_create(&path, 0).unwrap();
+	assert_eq!(mapped.len(), 1000);
+	assert_eq!((mapped[1], mapped[999]), (10, 20));
+	mapped.resize(2).unwrap();
+	drop(mapped);
+
+	let heap = HeapArray::<u64>::read_from(File::open(&path).unwrap()).unwrap();
+	assert_eq!(&heap[..], &[0, 10]);
+	std::fs::remove_file(&path).unwrap();
+    }
+}
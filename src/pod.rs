@@ -0,0 +1,17 @@
+/// Plain old data. Types that can be safely copied to and from raw bytes.
+///
+/// # Safety
+/// Implementors must be `Copy`, contain no padding bytes, no pointers or references, and be valid for any bit pattern (including all zeroes).
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! pod {
+    ($($type:ty),*) => {
+	$(unsafe impl Pod for $type {})*
+    };
+}
+
+pod!(u8, u16, u32, u64, u128, usize,
+     i8, i16, i32, i64, i128, isize,
+     f32, f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
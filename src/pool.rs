@@ -0,0 +1,379 @@
+use super::*;
+use std::{
+    cell::RefCell,
+    sync::{
+	Mutex,
+	MutexGuard,
+	PoisonError,
+    },
+};
+
+/// Smallest size class in bytes.
+const MIN_CLASS_SHIFT: u32 = 6;
+/// Number of power-of-two size classes, from 64 bytes to 1MiB. Larger buffers are not pooled.
+const CLASSES: usize = 15;
+/// Default number of buffers retained per size class.
+const MAX_RETAINED: usize = 64;
+/// Default number of buffers a `LocalCache` holds per size class before returning them to the pool.
+const LOCAL_RETAINED: usize = 8;
+
+/// The size class for `size` bytes, or `None` if it is too large to pool.
+fn size_class(size: usize) -> Option<usize>
+{
+    let class = size.max(1).checked_next_power_of_two()?.trailing_zeros().saturating_sub(MIN_CLASS_SHIFT) as usize;
+    if class < CLASSES {
+	Some(class)
+    } else {
+	None
+    }
+}
+
+/// Size in bytes of the buffers in `class`.
+fn class_size(class: usize) -> usize
+{
+    1 << (class as u32 + MIN_CLASS_SHIFT)
+}
+
+/// Pool of `HeapArray<u8>` buffers kept in free lists per power-of-two size class.
+///
+/// Buffers are handed out as `PooledBuffer`s that go back to the pool when dropped instead of being freed.
+#[derive(Debug)]
+pub struct BufferPool
+{
+    classes: Vec<Mutex<Vec<HeapArray<u8>>>>,
+    max_retained: usize,
+}
+
+/// Where a `PooledBuffer` returns to when dropped.
+#[derive(Debug, Clone, Copy)]
+enum Home<'a>
+{
+    Pool(&'a BufferPool),
+    Local(&'a LocalCache<'a>),
+}
+
+/// A buffer borrowed from a `BufferPool`. Dereferences to the requested number of bytes.
+///
+/// The contents of reused buffers are left over from their previous use.
+#[derive(Debug)]
+pub struct PooledBuffer<'a>
+{
+    array: Option<HeapArray<u8>>,
+    len: usize,
+    home: Home<'a>,
+}
+
+/// Unsynchronised per-thread front-end to a `BufferPool`.
+///
+/// Holds a few buffers per size class without locking, and returns them to the pool when it is dropped.
+#[derive(Debug)]
+pub struct LocalCache<'a>
+{
+    pool: &'a BufferPool,
+    classes: RefCell<Vec<Vec<HeapArray<u8>>>>,
+    max_retained: usize,
+}
+
+impl BufferPool
+{
+    /// Create a new empty pool.
+    pub fn new() -> Self
+    {
+	Self::with_max_retained(MAX_RETAINED)
+    }
+
+    /// Create a new empty pool retaining at most `max_retained` free buffers per size class.
+    pub fn with_max_retained(max_retained: usize) -> Self
+    {
+	Self {
+	    classes: (0..CLASSES).map(|_| Mutex::new(Vec::new())).collect(),
+	    max_retained,
+	}
+    }
+
+    fn class(&self, class: usize) -> MutexGuard<'_, Vec<HeapArray<u8>>>
+    {
+	self.classes[class].lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Take a free buffer from `class`, or allocate a new zeroed one.
+    fn take(&self, class: usize) -> HeapArray<u8>
+    {
+	self.class(class).pop().unwrap_or_else(|| HeapArray::new(class_size(class)))
+    }
+
+    /// Return a buffer to the free list of its class, freeing it if the list is full or it cannot be pooled.
+    fn put(&self, array: HeapArray<u8>)
+    {
+	if let Some(class) = size_class(array.len()).filter(|&x| class_size(x) == array.len()) {
+	    let mut free = self.class(class);
+	    if free.len() < self.max_retained {
+		free.push(array);
+	    }
+	}
+    }
+
+    /// Get a buffer of `size` bytes.
+    pub fn get(&self, size: usize) -> PooledBuffer<'_>
+    {
+	let array = match size_class(size) {
+	    Some(class) => self.take(class),
+	    None => HeapArray::new(size),
+	};
+	PooledBuffer {
+	    array: Some(array),
+	    len: size,
+	    home: Home::Pool(self),
+	}
+    }
+
+    /// Get a zeroed buffer of `size` bytes.
+    pub fn get_zeroed(&self, size: usize) -> PooledBuffer<'_>
+    {
+	let mut buffer = self.get(size);
+	buffer.fill(0);
+	buffer
+    }
+
+    /// Create an unsynchronised cache in front of this pool for use on one thread.
+    pub fn local(&self) -> LocalCache<'_>
+    {
+	LocalCache {
+	    pool: self,
+	    classes: RefCell::new((0..CLASSES).map(|_| Vec::new()).collect()),
+	    max_retained: LOCAL_RETAINED,
+	}
+    }
+
+    /// Number of free buffers retained.
+    pub fn retained(&self) -> usize
+    {
+	(0..CLASSES).map(|x| self.class(x).len()).sum()
+    }
+
+    /// Total size in bytes of the free buffers retained.
+    pub fn retained_bytes(&self) -> usize
+    {
+	(0..CLASSES).map(|x| self.class(x).len() * class_size(x)).sum()
+    }
+
+    /// Free all retained buffers.
+    pub fn trim(&self)
+    {
+	self.trim_to(0);
+    }
+
+    /// Free retained buffers, largest classes first, until at most `bytes` bytes are retained.
+    pub fn trim_to(&self, bytes: usize)
+    {
+	let mut retained = self.retained_bytes();
+	for class in (0..CLASSES).rev() {
+	    let mut free = self.class(class);
+	    while retained > bytes && free.pop().is_some() {
+		retained -= class_size(class);
+	    }
+	}
+    }
+}
+
+impl Default for BufferPool
+{
+    fn default() -> Self
+    {
+	Self::new()
+    }
+}
+
+impl<'a> LocalCache<'a>
+{
+    /// Get a buffer of `size` bytes, taking it from the pool if this cache has none of its size class.
+    pub fn get(&self, size: usize) -> PooledBuffer<'_>
+    {
+	let array = match size_class(size) {
+	    Some(class) => self.classes.borrow_mut()[class].pop().unwrap_or_else(|| self.pool.take(class)),
+	    None => HeapArray::new(size),
+	};
+	PooledBuffer {
+	    array: Some(array),
+	    len: size,
+	    home: Home::Local(self),
+	}
+    }
+
+    /// Get a zeroed buffer of `size` bytes.
+    pub fn get_zeroed(&self, size: usize) -> PooledBuffer<'_>
+    {
+	let mut buffer = self.get(size);
+	buffer.fill(0);
+	buffer
+    }
+
+    /// Keep a buffer in this cache, or pass it on to the pool if the cache is full.
+    fn put(&self, array: HeapArray<u8>)
+    {
+	if let Some(class) = size_class(array.len()).filter(|&x| class_size(x) == array.len()) {
+	    let mut classes = self.classes.borrow_mut();
+	    if classes[class].len() < self.max_retained {
+		classes[class].push(array);
+		return;
+	    }
+	}
+	self.pool.put(array);
+    }
+
+    /// Return all cached buffers to the pool.
+    pub fn flush(&self)
+    {
+	for array in self.classes.borrow_mut().iter_mut().flat_map(|x| x.drain(..)) {
+	    self.pool.put(array);
+	}
+    }
+}
+
+impl Drop for LocalCache<'_>
+{
+    fn drop(&mut self)
+    {
+	self.flush();
+    }
+}
+
+impl PooledBuffer<'_>
+{
+    fn array(&self) -> &HeapArray<u8>
+    {
+	self.array.as_ref().unwrap()
+    }
+
+    /// Number of bytes requested.
+    pub fn len(&self) -> usize
+    {
+	self.len
+    }
+
+    /// Is this buffer empty?
+    pub fn is_empty(&self) -> bool
+    {
+	self.len == 0
+    }
+
+    /// Size in bytes of the underlying buffer.
+    pub fn capacity(&self) -> usize
+    {
+	self.array().len()
+    }
+
+    /// As an immutable slice of the requested bytes.
+    pub fn as_slice(&self) -> &[u8]
+    {
+	&self.array()[..self.len]
+    }
+
+    /// As a mutable slice of the requested bytes.
+    pub fn as_slice_mut(&mut self) -> &mut [u8]
+    {
+	let len = self.len;
+	&mut self.array.as_mut().unwrap()[..len]
+    }
+
+    /// Consumes the instance, taking the buffer out of the pool as a `HeapArray<u8>` of the requested length.
+    pub fn into_heap_array(mut self) -> HeapArray<u8>
+    {
+	let array = self.array.take().unwrap();
+	if array.len() == self.len {
+	    array
+	} else {
+	    array.resize(self.len)
+	}
+    }
+}
+
+impl Drop for PooledBuffer<'_>
+{
+    fn drop(&mut self)
+    {
+	if let Some(array) = self.array.take() {
+	    match self.home {
+		Home::Pool(pool) => pool.put(array),
+		Home::Local(cache) => cache.put(array),
+	    }
+	}
+    }
+}
+
+impl Deref for PooledBuffer<'_>
+{
+    type Target = [u8];
+    fn deref(&self) -> &Self::Target
+    {
+	self.as_slice()
+    }
+}
+impl DerefMut for PooledBuffer<'_>
+{
+    fn deref_mut(&mut self) -> &mut Self::Target
+    {
+	self.as_slice_mut()
+    }
+}
+
+impl AsRef<[u8]> for PooledBuffer<'_>
+{
+    fn as_ref(&self) -> &[u8]
+    {
+	self.as_slice()
+    }
+}
+impl AsMut<[u8]> for PooledBuffer<'_>
+{
+    fn as_mut(&mut self) -> &mut [u8]
+    {
+	self.as_slice_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn reuse()
+    {
+	let pool = BufferPool::with_max_retained(1);
+	let mut buffer = pool.get(100);
+	assert_eq!((buffer.len(), buffer.capacity()), (100, 128));
+	buffer[99] = 1;
+	let ptr = buffer.as_ptr();
+	drop(buffer);
+	assert_eq!(pool.retained_bytes(), 128);
+
+	let first = pool.get(128);
+	let second = pool.get_zeroed(70);
+	assert_eq!(first.as_ptr(), ptr);
+	assert_eq!(&second[..], &[0; 70][..]);
+	drop((first, second));
+	assert_eq!(pool.retained(), 1);
+
+	drop(pool.get(1 << 21));
+	pool.trim();
+	assert_eq!(pool.retained(), 0);
+	assert_eq!(pool.get(5).into_heap_array().len(), 5);
+    }
+
+    #[test]
+    fn local()
+    {
+	let pool = BufferPool::new();
+	{
+	    let cache = pool.local();
+	    let ptr = cache.get(64).as_ptr();
+	    assert_eq!(cache.get(1).as_ptr(), ptr);
+	    assert_eq!(pool.retained(), 0);
+	}
+	assert_eq!(pool.retained(), 1);
+	pool.get(4096);
+	pool.trim_to(64);
+	assert_eq!(pool.retained_bytes(), 64);
+    }
+}
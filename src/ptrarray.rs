@@ -0,0 +1,182 @@
+use super::*;
+use store::Store;
+use cstring::NulError;
+use std::{
+    ffi::CStr,
+    os::raw::c_char,
+};
+
+/// A `NULL`-terminated array of pointers to arrays it owns, like `argv` and `envp`.
+///
+/// The arrays are kept in a `Store<T>`, and the pointers in a `HeapArray<*mut T>` that always ends in `NULL`.
+#[derive(Debug)]
+pub struct HeapPtrArray<T>
+{
+    store: Store<T>,
+    ptrs: HeapArray<*mut T>,
+}
+
+unsafe impl<T> Sync for HeapPtrArray<T>
+where T: Sync{}
+unsafe impl<T> Send for HeapPtrArray<T>
+where T: Send{}
+
+impl<T> HeapPtrArray<T>
+{
+    /// Create a new empty array, holding just the `NULL` terminator.
+    pub fn new() -> Self
+    {
+	Self {
+	    store: Store::new(),
+	    ptrs: HeapArray::new(1),
+	}
+    }
+
+    /// Number of pointers, not including the `NULL` terminator.
+    pub fn len(&self) -> usize
+    {
+	self.ptrs.len() - 1
+    }
+
+    /// Is the array empty apart from the `NULL` terminator?
+    pub fn is_empty(&self) -> bool
+    {
+	self.len() == 0
+    }
+
+    /// Append a pointer to memory owned by `store`.
+    fn push_ptr(&mut self, ptr: *mut T)
+    {
+	let len = self.len();
+	self.ptrs.try_resize(len + 2).expect("realloc()");
+	self.ptrs[len] = ptr;
+	self.ptrs[len + 1] = ptr::null();
+    }
+
+    /// Append a copy of `from`. Any terminator the elements need must be included.
+    ///
+    /// # Panics
+    /// If `from` is empty, since there would be no allocation to point to.
+    pub fn push_slice(&mut self, from: &[T])
+    where T: Copy
+    {
+	assert!(!from.is_empty(), "push_slice() of an empty slice");
+	let ptr = self.store.alloc_slice_copy(from).as_mut_ptr();
+	self.push_ptr(ptr);
+    }
+
+    /// The pointer at `index`.
+    pub fn get(&self, index: usize) -> Option<*const T>
+    {
+	self.ptrs[..self.len()].get(index).map(|&x| x as *const T)
+    }
+
+    /// Pointer to the `NULL`-terminated array of pointers, valid until this instance is changed or dropped.
+    pub fn as_ptr(&self) -> *const *const T
+    {
+	self.ptrs.as_ptr() as *const *const T
+    }
+}
+
+impl HeapPtrArray<c_char>
+{
+    /// Append a copy of a C string.
+    pub fn push<S: AsRef<CStr>>(&mut self, string: S)
+    {
+	let bytes = string.as_ref().to_bytes_with_nul();
+	let chars = unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const c_char, bytes.len()) };
+	self.push_slice(chars);
+    }
+
+    /// Append a copy of a string, adding the NUL terminator.
+    ///
+    /// # Errors
+    /// If `string` contains a NUL character.
+    pub fn push_str(&mut self, string: &str) -> Result<(), NulError>
+    {
+	self.push(HeapCString::new(string)?);
+	Ok(())
+    }
+
+    /// Create an array of copies of the strings.
+    ///
+    /// # Errors
+    /// If any of the strings contain a NUL character.
+    pub fn from_strs<I, S>(strings: I) -> Result<Self, NulError>
+    where I: IntoIterator<Item = S>,
+	  S: AsRef<str>
+    {
+	let mut output = Self::new();
+	for string in strings {
+	    output.push_str(string.as_ref())?;
+	}
+	Ok(output)
+    }
+
+    /// Create an array by copying the strings of a foreign `NULL`-terminated array such as `argv` or `environ`.
+    ///
+    /// # Safety
+    /// `ptr` must point to a `NULL`-terminated array of pointers to NUL-terminated strings.
+    pub unsafe fn from_null_terminated(ptr: *const *const c_char) -> Self
+    {
+	let mut output = Self::new();
+	let mut at = ptr;
+	while !(*at).is_null() {
+	    output.push(CStr::from_ptr(*at));
+	    at = at.add(1);
+	}
+	output
+    }
+
+    /// Iterate over the strings.
+    pub fn iter(&self) -> impl Iterator<Item = &CStr> + '_
+    {
+	self.ptrs[..self.len()].iter().map(|&x| unsafe { CStr::from_ptr(x) })
+    }
+}
+
+impl<T> Default for HeapPtrArray<T>
+{
+    fn default() -> Self
+    {
+	Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn argv()
+    {
+	let argv = HeapPtrArray::from_strs(["sh", "-c", "exit 3"]).unwrap();
+	assert_eq!(argv.len(), 3);
+	unsafe {
+	    assert!((*argv.as_ptr().add(3)).is_null());
+	    assert_eq!(CStr::from_ptr(*argv.as_ptr().add(2)).to_str(), Ok("exit 3"));
+	}
+
+	let copy = unsafe { HeapPtrArray::from_null_terminated(argv.as_ptr()) };
+	assert_eq!(copy.iter().map(|x| x.to_str().unwrap()).collect::<Vec<_>>(), ["sh", "-c", "exit 3"]);
+	assert_ne!(copy.get(0), argv.get(0));
+	assert!(HeapPtrArray::from_strs(["a\0b"]).is_err());
+	assert!(std::panic::catch_unwind(|| HeapPtrArray::<u8>::new().push_slice(&[])).is_err());
+    }
+
+    #[test]
+    fn spawn()
+    {
+	let argv = HeapPtrArray::from_strs(["sh", "-c", "exit $CODE"]).unwrap();
+	let envp = HeapPtrArray::from_strs(["CODE=3"]).unwrap();
+	unsafe {
+	    let mut pid = 0;
+	    let path = CStr::from_bytes_with_nul(b"/bin/sh\0").unwrap();
+	    assert_eq!(libc::posix_spawn(&mut pid, path.as_ptr(), std::ptr::null(), std::ptr::null(), argv.as_ptr() as *const *mut c_char, envp.as_ptr() as *const *mut c_char), 0);
+	    let mut status = 0;
+	    libc::waitpid(pid, &mut status, 0);
+	    assert_eq!(libc::WEXITSTATUS(status), 3);
+	}
+    }
+}
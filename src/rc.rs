@@ -0,0 +1,668 @@
+use super::*;
+use std::{
+    cell::Cell,
+    cmp,
+    marker::PhantomData,
+    mem,
+    ptr::NonNull,
+    sync::atomic::{
+	self,
+	AtomicUsize,
+	Ordering,
+    },
+};
+
+/// Counts above this abort the process, as `std::rc` and `std::sync` do, rather than risking a wrap after repeated `mem::forget()`s.
+const MAX_COUNT: usize = isize::MAX as usize;
+
+/// Value the weak count is set to while `is_unique()` has it locked.
+const LOCKED: usize = usize::MAX;
+
+/// A reference count, either thread-local or atomic.
+trait Counter
+{
+    fn new(value: usize) -> Self;
+    fn count(&self) -> usize;
+    /// Increment the count, waiting while it is locked. Aborts if it would pass `MAX_COUNT`.
+    fn increment(&self);
+    /// Decrement the count, returning the new value.
+    fn decrement(&self) -> usize;
+    /// Increment the count unless it is zero.
+    fn increment_if_nonzero(&self) -> bool;
+    /// Set the count from one to zero, if it is one.
+    fn release_unique(&self) -> bool;
+    /// Lock the count at `LOCKED` if it is one, so it cannot change until `unlock()`.
+    fn lock_unique(&self) -> bool;
+    /// Undo `lock_unique()`.
+    fn unlock(&self);
+    /// Synchronise with other threads' decrements before freeing.
+    fn acquire(&self);
+}
+
+impl Counter for Cell<usize>
+{
+    fn new(value: usize) -> Self
+    {
+	Cell::new(value)
+    }
+    fn count(&self) -> usize
+    {
+	self.get()
+    }
+    fn increment(&self)
+    {
+	if self.get() >= MAX_COUNT {
+	    std::process::abort();
+	}
+	self.set(self.get() + 1);
+    }
+    fn decrement(&self) -> usize
+    {
+	self.set(self.get() - 1);
+	self.get()
+    }
+    fn increment_if_nonzero(&self) -> bool
+    {
+	if self.get() == 0 {
+	    false
+	} else {
+	    self.increment();
+	    true
+	}
+    }
+    fn release_unique(&self) -> bool
+    {
+	if self.get() == 1 {
+	    self.set(0);
+	    true
+	} else {
+	    false
+	}
+    }
+    fn lock_unique(&self) -> bool
+    {
+	self.get() == 1
+    }
+    fn unlock(&self){}
+    fn acquire(&self){}
+}
+
+impl Counter for AtomicUsize
+{
+    fn new(value: usize) -> Self
+    {
+	AtomicUsize::new(value)
+    }
+    fn count(&self) -> usize
+    {
+	AtomicUsize::load(self, Ordering::Acquire)
+    }
+    fn increment(&self)
+    {
+	let mut current = AtomicUsize::load(self, Ordering::Relaxed);
+	loop {
+	    if current == LOCKED {
+		std::hint::spin_loop();
+		current = AtomicUsize::load(self, Ordering::Relaxed);
+		continue;
+	    }
+	    if current >= MAX_COUNT {
+		std::process::abort();
+	    }
+	    match self.compare_exchange_weak(current, current + 1, Ordering::Acquire, Ordering::Relaxed) {
+		Ok(_) => return,
+		Err(actual) => current = actual,
+	    }
+	}
+    }
+    fn decrement(&self) -> usize
+    {
+	self.fetch_sub(1, Ordering::Release) - 1
+    }
+    fn increment_if_nonzero(&self) -> bool
+    {
+	let mut current = AtomicUsize::load(self, Ordering::Relaxed);
+	while current != 0 {
+	    if current >= MAX_COUNT {
+		std::process::abort();
+	    }
+	    match self.compare_exchange_weak(current, current + 1, Ordering::Acquire, Ordering::Relaxed) {
+		Ok(_) => return true,
+		Err(actual) => current = actual,
+	    }
+	}
+	false
+    }
+    fn release_unique(&self) -> bool
+    {
+	self.compare_exchange(1, 0, Ordering::Acquire, Ordering::Relaxed).is_ok()
+    }
+    fn lock_unique(&self) -> bool
+    {
+	self.compare_exchange(1, LOCKED, Ordering::Acquire, Ordering::Relaxed).is_ok()
+    }
+    fn unlock(&self)
+    {
+	self.store(1, Ordering::Release);
+    }
+    fn acquire(&self)
+    {
+	atomic::fence(Ordering::Acquire);
+    }
+}
+
+/// Start of the allocation, before the elements.
+///
+/// The strong references together hold one weak reference, so the block is freed when `weak` reaches zero.
+#[repr(C)]
+struct Header<C>
+{
+    strong: C,
+    weak: C,
+    len: usize,
+    /// Drop the elements with the last strong reference. Taken from `HeapArray::drop_check`.
+    drop_check: bool,
+}
+
+impl<C: Counter> Header<C>
+{
+    /// Bytes between the start of the allocation and the first element.
+    fn offset<T>() -> usize
+    {
+	let align = mem::align_of::<T>();
+	mem::size_of::<Self>().div_ceil(align) * align
+    }
+
+    /// Alignment of the allocation.
+    fn align<T>() -> usize
+    {
+	cmp::max(mem::align_of::<T>(), cmp::max(mem::align_of::<Self>(), alloc::MALLOC_ALIGN))
+    }
+
+    fn bytes_for<T>(len: usize) -> Result<usize, Error>
+    {
+	len.checked_mul(mem::size_of::<T>())
+	    .and_then(|x| x.checked_add(Self::offset::<T>()))
+	    .ok_or(Error::Alloc)
+    }
+
+    unsafe fn data<T>(this: NonNull<Self>) -> *mut T
+    {
+	(this.as_ptr() as *mut u8).add(Self::offset::<T>()) as *mut T
+    }
+
+    unsafe fn slice<'a, T>(this: NonNull<Self>) -> &'a [T]
+    {
+	slice::from_raw_parts(Self::data(this), this.as_ref().len)
+    }
+
+    /// Allocate a block for `len` elements with one strong reference. The elements are uninitialised.
+    unsafe fn alloc<T>(len: usize) -> Result<NonNull<Self>, Error>
+    {
+	let bytes = Self::bytes_for::<T>(len)?;
+	let block = if Self::align::<T>() > alloc::MALLOC_ALIGN {
+	    alloc::aligned_malloc(Self::align::<T>(), bytes)?
+	} else {
+	    alloc::malloc(bytes)?
+	} as *mut Self;
+	block.write(Self::new(len));
+	Ok(NonNull::new_unchecked(block))
+    }
+
+    fn new(len: usize) -> Self
+    {
+	Self {
+	    strong: C::new(1),
+	    weak: C::new(1),
+	    len,
+	    drop_check: true,
+	}
+    }
+
+    /// Take the elements of `array`, growing its allocation with `realloc()` to fit the header in front of them if possible.
+    ///
+    /// # Errors
+    /// If allocation fails. `array` is dropped.
+    fn try_from_heap_array<T>(mut array: HeapArray<T>) -> Result<NonNull<Self>, Error>
+    {
+	let len = array.len();
+	let bytes = len * mem::size_of::<T>();
+	let drop_check = array.drop_check;
+	let total = Self::bytes_for::<T>(len)?;
+	array.make_owned()?;
+	unsafe {
+	    let block = if !array.as_ptr().is_null() && Self::align::<T>() <= alloc::MALLOC_ALIGN {
+		let block = NonNull::new_unchecked(alloc::realloc(array.as_ptr() as VoidPointer, total)? as *mut Self);
+		let _ = array.into_raw_parts();
+		ptr::memmove(Self::data::<T>(block) as VoidPointer, block.as_ptr() as ConstVoidPointer, bytes);
+		block.as_ptr().write(Self::new(len));
+		block
+	    } else {
+		let block = Self::alloc::<T>(len)?;
+		if bytes > 0 {
+		    ptr::memcpy(Self::data::<T>(block) as VoidPointer, array.as_ptr() as ConstVoidPointer, bytes);
+		}
+		array.free();
+		block
+	    };
+	    (*block.as_ptr()).drop_check = drop_check;
+	    Ok(block)
+	}
+    }
+
+    /// Allocate a block holding clones of `from`.
+    fn from_slice<T: Clone>(from: &[T]) -> NonNull<Self>
+    {
+	unsafe {
+	    let block = Self::alloc::<T>(from.len()).expect("malloc()");
+	    let data = Self::data::<T>(block);
+	    for (i, x) in from.iter().enumerate() {
+		data.add(i).write(x.clone());
+	    }
+	    block
+	}
+    }
+
+    /// Is `this` the only reference of either kind?
+    ///
+    /// The weak count is locked while the strong count is checked, so no weak reference can be upgraded in between.
+    unsafe fn is_unique(this: NonNull<Self>) -> bool
+    {
+	let header = this.as_ref();
+	if header.weak.lock_unique() {
+	    let unique = header.strong.count() == 1;
+	    header.weak.unlock();
+	    unique
+	} else {
+	    false
+	}
+    }
+
+    /// Drop a strong reference, dropping the elements if it was the last one.
+    unsafe fn release_strong<T>(this: NonNull<Self>)
+    {
+	if this.as_ref().strong.decrement() == 0 {
+	    this.as_ref().strong.acquire();
+	    if this.as_ref().drop_check {
+		std::ptr::drop_in_place(std::ptr::slice_from_raw_parts_mut(Self::data::<T>(this), this.as_ref().len));
+	    }
+	    Self::release_weak(this);
+	}
+    }
+
+    /// Drop a weak reference, freeing the block if it was the last one.
+    unsafe fn release_weak(this: NonNull<Self>)
+    {
+	if this.as_ref().weak.decrement() == 0 {
+	    this.as_ref().weak.acquire();
+	    alloc::free(this.as_ptr() as VoidPointer);
+	}
+    }
+
+    /// Move the elements out of a block whose last strong reference has been dropped without dropping them.
+    unsafe fn into_heap_array<T>(this: NonNull<Self>) -> HeapArray<T>
+    {
+	let len = this.as_ref().len;
+	let bytes = len * mem::size_of::<T>();
+	let data = Self::data::<T>(this);
+	let drop_check = this.as_ref().drop_check;
+	let mut output = if bytes > 0 && this.as_ref().weak.count() == 1 && Self::align::<T>() <= alloc::MALLOC_ALIGN {
+	    ptr::memmove(this.as_ptr() as VoidPointer, data as ConstVoidPointer, bytes);
+	    let ptr = alloc::realloc(this.as_ptr() as VoidPointer, bytes).unwrap_or(this.as_ptr() as VoidPointer);
+	    HeapArray::from_raw_parts(ptr as *mut T, len)
+	} else {
+	    let output = HeapArray::new_uninit(len);
+	    ptr::memcpy(output.as_ptr() as VoidPointer, data as ConstVoidPointer, bytes);
+	    Self::release_weak(this);
+	    output
+	};
+	output.drop_check = drop_check;
+	output
+    }
+}
+
+macro_rules! shared {
+    ($(#[$meta:meta])* $name:ident, $(#[$weak_meta:meta])* $weak:ident, $counter:ty) => {
+	$(#[$meta])*
+	pub struct $name<T>
+	{
+	    block: NonNull<Header<$counter>>,
+	    _marker: PhantomData<T>,
+	}
+
+	$(#[$weak_meta])*
+	pub struct $weak<T>
+	{
+	    block: NonNull<Header<$counter>>,
+	    _marker: PhantomData<T>,
+	}
+
+	impl<T> $name<T>
+	{
+	    fn from_block(block: NonNull<Header<$counter>>) -> Self
+	    {
+		Self {
+		    block,
+		    _marker: PhantomData,
+		}
+	    }
+
+	    /// Take the elements of `array`, growing its allocation to fit the reference counts in front of them if possible.
+	    ///
+	    /// # Errors
+	    /// If allocation fails. `array` is dropped.
+	    pub fn try_from_heap_array(array: HeapArray<T>) -> Result<Self, Error>
+	    {
+		Ok(Self::from_block(Header::try_from_heap_array(array)?))
+	    }
+
+	    /// Create a new instance holding clones of `from`.
+	    pub fn from_slice(from: &[T]) -> Self
+	    where T: Clone
+	    {
+		Self::from_block(Header::from_slice(from))
+	    }
+
+	    /// As an immutable slice of `T`.
+	    pub fn as_slice(&self) -> &[T]
+	    {
+		unsafe { Header::slice(self.block) }
+	    }
+
+	    /// Create a weak reference to the elements.
+	    pub fn downgrade(this: &Self) -> $weak<T>
+	    {
+		unsafe { this.block.as_ref().weak.increment() };
+		$weak {
+		    block: this.block,
+		    _marker: PhantomData,
+		}
+	    }
+
+	    /// Number of strong references.
+	    pub fn strong_count(this: &Self) -> usize
+	    {
+		unsafe { this.block.as_ref().strong.count() }
+	    }
+
+	    /// Number of weak references.
+	    pub fn weak_count(this: &Self) -> usize
+	    {
+		match unsafe { this.block.as_ref().weak.count() } {
+		    LOCKED => 0,
+		    weak => weak - 1,
+		}
+	    }
+
+	    /// Do both instances share the same elements?
+	    pub fn ptr_eq(this: &Self, other: &Self) -> bool
+	    {
+		this.block == other.block
+	    }
+
+	    /// A mutable slice of the elements, if there are no other references of either kind.
+	    pub fn get_mut(this: &mut Self) -> Option<&mut [T]>
+	    {
+		unsafe {
+		    if Header::is_unique(this.block) {
+			Some(slice::from_raw_parts_mut(Header::data(this.block), this.block.as_ref().len))
+		    } else {
+			None
+		    }
+		}
+	    }
+
+	    /// A mutable slice of the elements, cloning them into a new allocation first if there are other references of either kind.
+	    pub fn make_mut(this: &mut Self) -> &mut [T]
+	    where T: Clone
+	    {
+		unsafe {
+		    if !Header::is_unique(this.block) {
+			*this = Self::from_slice(this.as_slice());
+		    }
+		    slice::from_raw_parts_mut(Header::data(this.block), this.block.as_ref().len)
+		}
+	    }
+
+	    /// Move the elements into a `HeapArray<T>` if this is the only strong reference. The allocation is shrunk with `realloc()` if there are no weak references either.
+	    ///
+	    /// # Errors
+	    /// The instance is returned if there are other strong references.
+	    pub fn try_unwrap(this: Self) -> Result<HeapArray<T>, Self>
+	    {
+		unsafe {
+		    if !this.block.as_ref().strong.release_unique() {
+			return Err(this);
+		    }
+		    let block = this.block;
+		    mem::forget(this);
+		    Ok(Header::into_heap_array(block))
+		}
+	    }
+	}
+
+	impl<T> $weak<T>
+	{
+	    /// Get a strong reference, if the elements have not been dropped.
+	    pub fn upgrade(&self) -> Option<$name<T>>
+	    {
+		unsafe {
+		    if self.block.as_ref().strong.increment_if_nonzero() {
+			Some($name::from_block(self.block))
+		    } else {
+			None
+		    }
+		}
+	    }
+
+	    /// Number of strong references.
+	    pub fn strong_count(&self) -> usize
+	    {
+		unsafe { self.block.as_ref().strong.count() }
+	    }
+	}
+
+	impl<T> Drop for $name<T>
+	{
+	    fn drop(&mut self)
+	    {
+		unsafe { Header::release_strong::<T>(self.block) };
+	    }
+	}
+
+	impl<T> Drop for $weak<T>
+	{
+	    fn drop(&mut self)
+	    {
+		unsafe { Header::release_weak(self.block) };
+	    }
+	}
+
+	impl<T> Clone for $name<T>
+	{
+	    fn clone(&self) -> Self
+	    {
+		unsafe { self.block.as_ref().strong.increment() };
+		Self::from_block(self.block)
+	    }
+	}
+
+	impl<T> Clone for $weak<T>
+	{
+	    fn clone(&self) -> Self
+	    {
+		unsafe { self.block.as_ref().weak.increment() };
+		Self {
+		    block: self.block,
+		    _marker: PhantomData,
+		}
+	    }
+	}
+
+	impl<T> From<HeapArray<T>> for $name<T>
+	{
+	    fn from(from: HeapArray<T>) -> Self
+	    {
+		Self::try_from_heap_array(from).expect("malloc()")
+	    }
+	}
+
+	impl<T> From<&[T]> for $name<T>
+	where T: Clone
+	{
+	    fn from(from: &[T]) -> Self
+	    {
+		Self::from_slice(from)
+	    }
+	}
+
+	impl<T> Deref for $name<T>
+	{
+	    type Target = [T];
+	    fn deref(&self) -> &Self::Target
+	    {
+		self.as_slice()
+	    }
+	}
+
+	impl<T> AsRef<[T]> for $name<T>
+	{
+	    fn as_ref(&self) -> &[T]
+	    {
+		self.as_slice()
+	    }
+	}
+
+	impl<T> fmt::Debug for $name<T>
+	where T: fmt::Debug
+	{
+	    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+	    {
+		write!(f, "{}: {:?}", std::any::type_name::<Self>(), self.as_slice())
+	    }
+	}
+
+	impl<T> fmt::Debug for $weak<T>
+	{
+	    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+	    {
+		write!(f, "({})", std::any::type_name::<Self>())
+	    }
+	}
+    };
+}
+
+shared!(
+    /// A shared `[T]` with its reference counts in the same `malloc()` block as the elements. Not thread-safe.
+    HeapRc,
+    /// A weak reference to the elements of a `HeapRc<T>`. It keeps the block allocated but not the elements alive.
+    WeakHeapRc,
+    Cell<usize>
+);
+
+shared!(
+    /// A thread-safe shared `[T]` with its atomic reference counts in the same `malloc()` block as the elements.
+    HeapArc,
+    /// A weak reference to the elements of a `HeapArc<T>`. It keeps the block allocated but not the elements alive.
+    WeakHeapArc,
+    AtomicUsize
+);
+
+unsafe impl<T> Sync for HeapArc<T>
+where T: Sync + Send{}
+unsafe impl<T> Send for HeapArc<T>
+where T: Sync + Send{}
+unsafe impl<T> Sync for WeakHeapArc<T>
+where T: Sync + Send{}
+unsafe impl<T> Send for WeakHeapArc<T>
+where T: Sync + Send{}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn rc()
+    {
+	let array = heap![1u32, 2, 3];
+	let mut rc = HeapRc::from(array);
+	assert_eq!(&rc[..], &[1, 2, 3]);
+
+	let other = rc.clone();
+	let weak = HeapRc::downgrade(&rc);
+	assert_eq!(HeapRc::strong_count(&rc), 2);
+	assert_eq!(HeapRc::weak_count(&rc), 1);
+	assert!(HeapRc::get_mut(&mut rc).is_none());
+
+	HeapRc::make_mut(&mut rc)[0] = 10;
+	assert!(!HeapRc::ptr_eq(&rc, &other));
+	assert_eq!(&other[..], &[1, 2, 3]);
+	assert_eq!(&rc[..], &[10, 2, 3]);
+
+	let other = HeapRc::try_unwrap(other).unwrap();
+	assert_eq!(&other[..], &[1, 2, 3]);
+	assert!(weak.upgrade().is_none());
+	assert_eq!(&HeapRc::try_unwrap(rc).unwrap()[..], &[10, 2, 3]);
+    }
+
+    #[test]
+    fn arc()
+    {
+	let arc = HeapArc::from(&["a".to_owned(), "b".to_owned()][..]);
+	let weak = HeapArc::downgrade(&arc);
+	let threads: Vec<_> = (0..4).map(|_| {
+	    let arc = arc.clone();
+	    std::thread::spawn(move || arc.concat())
+	}).collect();
+	for thread in threads {
+	    assert_eq!(thread.join().unwrap(), "ab");
+	}
+	assert_eq!(weak.upgrade().map(|x| x.len()), Some(2));
+	let mut arc = arc;
+	let upgraded = weak.upgrade().unwrap();
+	assert!(HeapArc::get_mut(&mut arc).is_none());
+	drop(upgraded);
+	assert!(HeapArc::get_mut(&mut arc).is_none());
+	assert_eq!(HeapArc::weak_count(&arc), 1);
+	let arc = HeapArc::try_unwrap(arc).unwrap();
+	assert_eq!(weak.strong_count(), 0);
+	assert!(weak.upgrade().is_none());
+	assert_eq!(&arc[..], &["a", "b"]);
+
+	let empty = HeapArc::<u64>::from(HeapArray::new(0));
+	assert!(empty.is_empty());
+	assert_eq!(HeapArc::try_unwrap(empty).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn from_heap_array()
+    {
+	let counted = std::rc::Rc::new(());
+	let mut array = heap![counted.clone(), counted.clone()];
+	array.drop_check = false;
+	let rc = HeapRc::from(array);
+	let array = HeapRc::try_unwrap(rc).unwrap();
+	assert!(!array.drop_check);
+	drop(HeapRc::from(array));
+	assert_eq!(std::rc::Rc::strong_count(&counted), 3);
+
+	drop(HeapRc::from(heap![counted.clone()]));
+	assert_eq!(std::rc::Rc::strong_count(&counted), 3);
+
+	let zst = HeapArc::<()>::from(HeapArray::new(2));
+	assert_eq!(&zst[..], &[(), ()]);
+	assert!(HeapRc::<u32>::try_from_heap_array(HeapArray::new(0)).unwrap().is_empty());
+    }
+
+    #[cfg(feature="fault_inject")]
+    #[test]
+    fn from_heap_array_fail()
+    {
+	let counted = std::rc::Rc::new(());
+	let array = heap![counted.clone(), counted.clone()];
+	let _guard = fault::Injector::new().fail_nth(1).install();
+	assert!(HeapRc::try_from_heap_array(array).is_err());
+	assert_eq!(std::rc::Rc::strong_count(&counted), 1);
+    }
+}
@@ -0,0 +1,133 @@
+use super::*;
+use std::error;
+
+/// No terminator was found within the maximum length when scanning a sentinel-terminated array.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnterminatedError
+{
+    max_len: usize,
+}
+
+impl UnterminatedError
+{
+    /// The number of elements scanned.
+    pub fn max_len(&self) -> usize
+    {
+	self.max_len
+    }
+}
+
+impl error::Error for UnterminatedError{}
+impl fmt::Display for UnterminatedError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	write!(f, "No terminator found in the first {} elements.", self.max_len)
+    }
+}
+
+/// Number of elements before the first one `is_end` accepts, scanning at most `max_len` elements.
+unsafe fn sentinel_len<T, F>(ptr: *const T, max_len: usize, mut is_end: F) -> Result<usize, UnterminatedError>
+where F: FnMut(&T) -> bool
+{
+    (0..max_len).find(|&i| is_end(&*ptr.add(i))).ok_or(UnterminatedError{max_len})
+}
+
+impl<T> HeapArray<T>
+{
+    /// Create a new instance by copying the elements before the terminator `is_end` accepts. The terminator is not copied.
+    ///
+    /// # Safety
+    /// `ptr` must point to initialised elements up to and including the terminator, or to `max_len` elements.
+    ///
+    /// # Errors
+    /// If there is no terminator in the first `max_len` elements.
+    pub unsafe fn from_sentinel_terminated<F>(ptr: *const T, max_len: usize, is_end: F) -> Result<Self, UnterminatedError>
+    where F: FnMut(&T) -> bool,
+	  T: Copy
+    {
+	let len = sentinel_len(ptr, max_len, is_end)?;
+	Ok(Self::from_raw_copied(ptr, len))
+    }
+
+    /// Take ownership of a `malloc()`ed sentinel-terminated array. The new instance includes the terminator as its last element.
+    ///
+    /// # Safety
+    /// `ptr` must be allocated by this crate's allocator backend and point to initialised elements up to and including the terminator, or to `max_len` elements.
+    ///
+    /// # Errors
+    /// If there is no terminator in the first `max_len` elements. Ownership is not taken.
+    pub unsafe fn adopt_sentinel_terminated<F>(ptr: *mut T, max_len: usize, is_end: F) -> Result<Self, UnterminatedError>
+    where F: FnMut(&T) -> bool
+    {
+	let len = sentinel_len(ptr, max_len, is_end)?;
+	Ok(Self::from_raw_parts(ptr, len + 1))
+    }
+}
+
+macro_rules! null_terminated {
+    ($($ptr:ty),*) => {
+	$(
+	    impl<U> HeapArray<$ptr>
+	    {
+		/// Create a new instance by copying the pointers before the terminating `NULL`. The terminator is not copied.
+		///
+		/// # Safety
+		/// `ptr` must point to pointers up to and including the `NULL` terminator, or to `max_len` pointers.
+		///
+		/// # Errors
+		/// If there is no `NULL` in the first `max_len` pointers.
+		pub unsafe fn from_null_terminated(ptr: *const $ptr, max_len: usize) -> Result<Self, UnterminatedError>
+		{
+		    Self::from_sentinel_terminated(ptr, max_len, |x| x.is_null())
+		}
+
+		/// Take ownership of a `malloc()`ed `NULL`-terminated array of pointers. The new instance includes the `NULL` as its last element.
+		///
+		/// # Safety
+		/// `ptr` must be allocated by this crate's allocator backend and point to pointers up to and including the `NULL` terminator, or to `max_len` pointers.
+		///
+		/// # Errors
+		/// If there is no `NULL` in the first `max_len` pointers. Ownership is not taken.
+		pub unsafe fn adopt_null_terminated(ptr: *mut $ptr, max_len: usize) -> Result<Self, UnterminatedError>
+		{
+		    Self::adopt_sentinel_terminated(ptr, max_len, |x| x.is_null())
+		}
+	    }
+	)*
+    };
+}
+
+null_terminated!(*const U, *mut U);
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn sentinel()
+    {
+	let values = [3i32, 2, 1, -1, 7];
+	let array = unsafe { HeapArray::from_sentinel_terminated(values.as_ptr(), 16, |&x| x == -1) }.unwrap();
+	assert_eq!(&array[..], &[3, 2, 1]);
+	let error = unsafe { HeapArray::from_sentinel_terminated(values.as_ptr(), 3, |&x| x == -1) }.unwrap_err();
+	assert_eq!(error.max_len(), 3);
+
+	let owned = heap![5u64, 6, 0].into_raw_parts().0;
+	let adopted = unsafe { HeapArray::adopt_sentinel_terminated(owned, 3, |&x| x == 0) }.unwrap();
+	assert_eq!(&adopted[..], &[5, 6, 0]);
+    }
+
+    #[test]
+    fn null()
+    {
+	let strings = [b"a\0".as_ptr(), b"b\0".as_ptr(), std::ptr::null()];
+	let array = unsafe { HeapArray::<*const u8>::from_null_terminated(strings.as_ptr(), usize::MAX) }.unwrap();
+	assert_eq!(&array[..], &strings[..2]);
+
+	let owned = heap![std::ptr::null_mut::<u8>()].into_raw_parts().0;
+	let adopted = unsafe { HeapArray::<*mut u8>::adopt_null_terminated(owned, 1) }.unwrap();
+	assert_eq!(adopted.len(), 1);
+    }
+}
@@ -0,0 +1,138 @@
+use super::*;
+use pod::Pod;
+use std::{
+    io::{
+	self,
+	Read,Write,
+    },
+    mem,
+    convert::TryFrom,
+};
+
+/// Magic bytes at the start of every serialized array.
+pub(crate) const MAGIC: [u8; 8] = *b"MALLOCAR";
+/// Version of the header format.
+pub(crate) const VERSION: u16 = 1;
+/// Size of the header in bytes. Element data starts at this offset.
+pub(crate) const HEADER_SIZE: usize = 32;
+
+/// Header written before the element data of a serialized `HeapArray<T>`. All fields are native-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub(crate) struct Header
+{
+    pub magic: [u8; 8],
+    pub version: u16,
+    pub header_size: u16,
+    pub element_size: u32,
+    pub element_align: u32,
+    _reserved: u32,
+    pub len: u64,
+}
+
+const _: () = assert!(mem::size_of::<Header>() == HEADER_SIZE);
+
+fn invalid(msg: &'static str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl Header
+{
+    /// Create a header for `len` elements of `T`.
+    pub fn new<T: Pod>(len: usize) -> Self
+    {
+	Self {
+	    magic: MAGIC,
+	    version: VERSION,
+	    header_size: HEADER_SIZE as u16,
+	    element_size: mem::size_of::<T>() as u32,
+	    element_align: mem::align_of::<T>() as u32,
+	    _reserved: 0,
+	    len: len as u64,
+	}
+    }
+
+    /// Check the header describes an array of `T`.
+    pub fn validate<T: Pod>(&self) -> io::Result<()>
+    {
+	if self.magic != MAGIC {
+	    Err(invalid("bad magic bytes"))
+	} else if self.version != VERSION || self.header_size as usize != HEADER_SIZE {
+	    Err(invalid("unsupported header version or byte order"))
+	} else if self.element_size as usize != mem::size_of::<T>() || self.element_align as usize != mem::align_of::<T>() {
+	    Err(invalid("element type does not match"))
+	} else if mem::align_of::<T>() > HEADER_SIZE {
+	    Err(invalid("element alignment is larger than the header"))
+	} else {
+	    Ok(())
+	}
+    }
+
+    /// Number of elements, if addressable.
+    pub fn len(&self) -> io::Result<usize>
+    {
+	usize::try_from(self.len).map_err(|_| invalid("length does not fit in memory"))
+    }
+
+    pub fn as_bytes(&self) -> &[u8]
+    {
+	unsafe {
+	    slice::from_raw_parts(self as *const Self as *const u8, HEADER_SIZE)
+	}
+    }
+
+    pub fn from_bytes(bytes: &[u8; HEADER_SIZE]) -> Self
+    {
+	unsafe {
+	    std::ptr::read_unaligned(bytes.as_ptr() as *const Self)
+	}
+    }
+}
+
+impl<T: Pod> HeapArray<T>
+{
+    /// Serialize the array with a header describing the element type and length.
+    ///
+    /// The output can be read back with `read_from()`, or mapped directly with `MappedArray::open()`.
+    pub fn write_to<W: Write>(&self, mut to: W) -> io::Result<()>
+    {
+	to.write_all(Header::new::<T>(self.len()).as_bytes())?;
+	to.write_all(self.memory())
+    }
+
+    /// Deserialize an array written by `write_to()`.
+    pub fn read_from<R: Read>(mut from: R) -> io::Result<Self>
+    {
+	let mut bytes = [0u8; HEADER_SIZE];
+	from.read_exact(&mut bytes)?;
+	let header = Header::from_bytes(&bytes);
+	header.validate::<T>()?;
+
+	let len = header.len()?;
+	len.checked_mul(mem::size_of::<T>()).ok_or_else(|| invalid("length does not fit in memory"))?;
+	let mut output = HeapArray::try_new_uninit(len).map_err(|_| invalid("length too large to allocate"))?;
+	from.read_exact(unsafe { output.memory_mut() })?;
+	Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn hostile_len()
+    {
+	let mut bytes = Vec::new();
+	heap![1u64, 2].write_to(&mut bytes).unwrap();
+	assert_eq!(&HeapArray::<u64>::read_from(&bytes[..]).unwrap()[..], &[1, 2]);
+
+	for &len in &[u64::MAX, u64::MAX / 8] {
+	    bytes[24..32].copy_from_slice(&len.to_ne_bytes());
+	    let err = HeapArray::<u64>::read_from(&bytes[..]).unwrap_err();
+	    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+	}
+    }
+}
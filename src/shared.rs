@@ -0,0 +1,266 @@
+use super::*;
+use pod::Pod;
+use mapped::MappedArray;
+use serial::{
+    Header,
+    HEADER_SIZE,
+};
+use std::{
+    io,
+    fs::File,
+    ffi::CString,
+    mem,
+    os::unix::{
+	io::{
+	    AsRawFd,
+	    FromRawFd,
+	    IntoRawFd,
+	    RawFd,
+	},
+	net::UnixStream,
+    },
+};
+
+/// Array in shared memory (`memfd_create()` or `shm_open()`) that can be sent to other processes.
+///
+/// The memory has the same layout as `MappedArray<T>` and dereferences to it for the slice interface.
+#[derive(Debug)]
+pub struct SharedHeapArray<T: Pod>
+{
+    inner: MappedArray<T>,
+}
+
+fn os_fd(fd: RawFd) -> io::Result<File>
+{
+    if fd == -1 {
+	Err(io::Error::last_os_error())
+    } else {
+	Ok(unsafe { File::from_raw_fd(fd) })
+    }
+}
+
+fn shm_name(name: &str) -> io::Result<CString>
+{
+    CString::new(name).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "name contains a NUL byte"))
+}
+
+impl<T: Pod> SharedHeapArray<T>
+{
+    /// Create an anonymous shared array of `len` zeroed elements with `memfd_create()`.
+    pub fn new(len: usize) -> io::Result<Self>
+    {
+	let name = shm_name("malloc-array")?;
+	let file = os_fd(unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC) })?;
+	Self::create_in(file, len)
+    }
+
+    /// Open the POSIX shared memory object `name` with `shm_open()`, creating it with `len` zeroed elements if it does not exist.
+    pub fn open_shm(name: &str, len: usize) -> io::Result<Self>
+    {
+	let name = shm_name(name)?;
+	let file = os_fd(unsafe { libc::shm_open(name.as_ptr(), libc::O_RDWR | libc::O_CREAT | libc::O_CLOEXEC, 0o600) })?;
+	if file.metadata()?.len() == 0 {
+	    Self::create_in(file, len)
+	} else {
+	    Self::from_file(file)
+	}
+    }
+
+    /// Remove the POSIX shared memory object `name`. Existing mappings stay valid.
+    pub fn unlink_shm(name: &str) -> io::Result<()>
+    {
+	let name = shm_name(name)?;
+	if unsafe { libc::shm_unlink(name.as_ptr()) } == -1 {
+	    Err(io::Error::last_os_error())
+	} else {
+	    Ok(())
+	}
+    }
+
+    fn create_in(file: File, len: usize) -> io::Result<Self>
+    {
+	let size = mem::size_of::<T>().checked_mul(len)
+	    .and_then(|x| x.checked_add(HEADER_SIZE))
+	    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "array too large to map"))?;
+	file.set_len(size as u64)?;
+	let mut bytes = [0u8; HEADER_SIZE];
+	bytes.copy_from_slice(Header::new::<T>(len).as_bytes());
+	std::os::unix::fs::FileExt::write_all_at(&file, &bytes, 0)?;
+	Self::from_file(file)
+    }
+
+    /// Map a shared memory file descriptor containing an array of `T`.
+    ///
+    /// # Errors
+    /// If the memory does not hold an array of `T`.
+    pub fn from_file(file: File) -> io::Result<Self>
+    {
+	Ok(Self {
+	    inner: MappedArray::from_file(file)?,
+	})
+    }
+
+    /// The file descriptor of the shared memory.
+    pub fn as_raw_fd(&self) -> RawFd
+    {
+	self.inner.file().as_raw_fd()
+    }
+
+    /// Consumes the instance, unmapping the memory and returning an owned file descriptor for it.
+    pub fn into_raw_fd(self) -> RawFd
+    {
+	self.inner.into_file().into_raw_fd()
+    }
+
+    /// Send the file descriptor with `SCM_RIGHTS` over a Unix domain socket, along with the length and type of the array.
+    pub fn send(&self, socket: &UnixStream) -> io::Result<()>
+    {
+	let header = Header::new::<T>(self.len());
+	let mut iov = libc::iovec {
+	    iov_base: header.as_bytes().as_ptr() as *mut libc::c_void,
+	    iov_len: HEADER_SIZE,
+	};
+	let mut control = [0u64; 8];
+	unsafe {
+	    let mut msg: libc::msghdr = mem::zeroed();
+	    msg.msg_iov = &mut iov;
+	    msg.msg_iovlen = 1;
+	    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+	    msg.msg_controllen = libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) as _;
+
+	    let cmsg = libc::CMSG_FIRSTHDR(&msg);
+	    (*cmsg).cmsg_level = libc::SOL_SOCKET;
+	    (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+	    (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<RawFd>() as u32) as _;
+	    std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, self.as_raw_fd());
+
+	    if libc::sendmsg(socket.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) == -1 {
+		return Err(io::Error::last_os_error());
+	    }
+	}
+	Ok(())
+    }
+
+    /// Receive an array sent with `send()` and map it into this process.
+    ///
+    /// # Errors
+    /// If no file descriptor was received, or the sent length or type does not match the shared memory or `T`.
+    pub fn recv(socket: &UnixStream) -> io::Result<Self>
+    {
+	let mut bytes = [0u8; HEADER_SIZE];
+	let mut iov = libc::iovec {
+	    iov_base: bytes.as_mut_ptr() as *mut libc::c_void,
+	    iov_len: HEADER_SIZE,
+	};
+	let mut control = [0u64; 8];
+	let file = unsafe {
+	    let mut msg: libc::msghdr = mem::zeroed();
+	    msg.msg_iov = &mut iov;
+	    msg.msg_iovlen = 1;
+	    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+	    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+	    let read = libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC);
+	    if read == -1 {
+		return Err(io::Error::last_os_error());
+	    }
+
+	    let mut file = None;
+	    let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+	    while !cmsg.is_null() {
+		if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+		    file = Some(File::from_raw_fd(std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const RawFd)));
+		}
+		cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+	    }
+	    if read as usize != HEADER_SIZE || msg.msg_flags & libc::MSG_CTRUNC != 0 {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated shared array message"));
+	    }
+	    file.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no file descriptor received"))?
+	};
+
+	let header = Header::from_bytes(&bytes);
+	header.validate::<T>()?;
+	let this = Self::from_file(file)?;
+	if header.len()? != this.len() {
+	    return Err(io::Error::new(io::ErrorKind::InvalidData, "sent length does not match shared memory"));
+	}
+	Ok(this)
+    }
+
+    /// Copy the elements into a new `HeapArray<T>`.
+    pub fn to_heap_array(&self) -> HeapArray<T>
+    {
+	self.inner.to_heap_array()
+    }
+}
+
+impl<T: Pod> Deref for SharedHeapArray<T>
+{
+    type Target = MappedArray<T>;
+    fn deref(&self) -> &Self::Target
+    {
+	&self.inner
+    }
+}
+impl<T: Pod> DerefMut for SharedHeapArray<T>
+{
+    fn deref_mut(&mut self) -> &mut Self::Target
+    {
+	&mut self.inner
+    }
+}
+
+impl<T: Pod> AsRef<[T]> for SharedHeapArray<T>
+{
+    fn as_ref(&self) -> &[T]
+    {
+	self.inner.as_slice()
+    }
+}
+impl<T: Pod> AsMut<[T]> for SharedHeapArray<T>
+{
+    fn as_mut(&mut self) -> &mut [T]
+    {
+	self.inner.as_slice_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn send_recv()
+    {
+	let (left, right) = UnixStream::pair().unwrap();
+	let mut array = SharedHeapArray::<u32>::new(16).unwrap();
+	array[3] = 300;
+	array.send(&left).unwrap();
+
+	let mut other = SharedHeapArray::<u32>::recv(&right).unwrap();
+	assert_eq!(other.len(), 16);
+	assert_eq!(other[3], 300);
+	other[4] = 400;
+	assert_eq!(array[4], 400);
+
+	array.send(&left).unwrap();
+	assert!(SharedHeapArray::<u64>::recv(&right).is_err());
+
+	let fd = array.into_raw_fd();
+	let array = SharedHeapArray::<u32>::from_file(unsafe { File::from_raw_fd(fd) }).unwrap();
+	assert_eq!(array[4], 400);
+    }
+
+    #[test]
+    fn shm()
+    {
+	let name = format!("/malloc-array-test-{}", std::process::id());
+	let mut array = SharedHeapArray::<u8>::open_shm(&name, 4).unwrap();
+	array.copy_from_slice(b"shm!");
+	let other = SharedHeapArray::<u8>::open_shm(&name, 0).unwrap();
+	SharedHeapArray::<u8>::unlink_shm(&name).unwrap();
+	assert_eq!(&other[..], b"shm!");
+    }
+}
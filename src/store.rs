@@ -1,10 +1,80 @@
 use crate::*;
+use std::{
+    convert::TryFrom,
+    any::TypeId,
+    cell::RefCell,
+    marker::PhantomData,
+    sync::{
+	Arc,
+	Mutex,
+	MutexGuard,
+	PoisonError,
+    },
+    iter::FromIterator,
+};
 
 /// Statically typed pointer store. `free()`s and drops on drop.
 #[derive(Debug)]
 pub struct Store<T>
 {
-    pointers: Vec<*mut T>,
+    pointers: Vec<Entry<T>>,
+    budget: Option<Arc<Budget>>,
+}
+
+/// A pointer in a `Store<T>` and the number of elements it points to.
+#[derive(Debug)]
+struct Entry<T>
+{
+    ptr: *mut T,
+    len: usize,
+    charge: Option<budget::Charge>,
+}
+
+impl<T> Entry<T>
+{
+    fn single(ptr: *mut T) -> Self
+    {
+	Self {
+	    ptr,
+	    len: 1,
+	    charge: None,
+	}
+    }
+
+    /// Allocate memory for `len` elements, charged against `budget` if there is one.
+    ///
+    /// The entry should not be stored until it is initialised, so a panic while initialising won't drop uninitialised memory.
+    fn alloc(len: usize, budget: Option<&Arc<Budget>>) -> Result<Self, Error>
+    {
+	let size = len.checked_mul(std::mem::size_of::<T>()).ok_or(Error::Alloc)?;
+	let charge = match budget {
+	    Some(budget) => Some(budget::Charge::new(budget, size)?),
+	    None => None,
+	};
+	Ok(Self {
+	    ptr: unsafe { alloc::malloc(size)? } as *mut T,
+	    len,
+	    charge,
+	})
+    }
+
+    /// The elements of this entry. `NULL` (from `zst_noalloc`) is replaced with a dangling pointer.
+    unsafe fn as_slice_mut<'a>(&self) -> &'a mut [T]
+    {
+	let ptr = if self.ptr == ptr::null() {
+	    std::ptr::NonNull::dangling().as_ptr()
+	} else {
+	    self.ptr
+	};
+	slice::from_raw_parts_mut(ptr, self.len)
+    }
+
+    /// Drop the elements and free the memory.
+    unsafe fn destroy(self)
+    {
+	std::ptr::drop_in_place(self.as_slice_mut());
+	alloc::free(self.ptr as VoidPointer);
+    }
 }
 
 #[cfg(test)]
@@ -46,6 +116,135 @@ mod tests
 	assert_eq!(ha.len(), 10);
 	assert_eq!(&ha[..], &[0,1,2,3,4,5,6,7,8,9]);
     }
+    #[test]
+    fn alloc()
+    {
+	let mut store = Store::new();
+	store.alloc("one".to_owned()).push('!');
+	store.alloc_array(2)[1].push_str("three");
+	assert_eq!(store.alloc_array(0).len(), 0);
+	assert_eq!(store.len(), 3);
+
+	let ha = store.into_heap_array();
+	assert_eq!(&ha[..], &["one!", "", "three"]);
+
+	let mut store = Store::new();
+	store.alloc_slice_copy(&[1u8, 2, 3])[0] = 0;
+	store.alloc(4u8);
+	assert_eq!(&store.into_heap_array()[..], &[0, 2, 3, 4]);
+    }
+    #[test]
+    fn dyn_drop()
+    {
+	use std::rc::Rc;
+	let counter = Rc::new(());
+	let mut store = DynStore::new();
+	unsafe {
+	    let string = store.ptr(alloc::malloc(std::mem::size_of::<String>()).unwrap() as *mut String);
+	    std::ptr::write(string, "string".to_owned());
+	    for _ in 0..3 {
+		std::ptr::write(store.ptr(alloc::malloc(std::mem::size_of::<Rc<()>>()).unwrap() as *mut Rc<()>), counter.clone());
+	    }
+	    assert_eq!(Rc::strong_count(&counter), 4);
+
+	    let handle = string as VoidPointer;
+	    assert_eq!(store.downcast::<String>(handle).map(String::as_str), Some("string"));
+	    assert!(store.downcast::<u32>(handle).is_none());
+	    assert_eq!(store.size_of(handle), Some(std::mem::size_of::<String>()));
+	    store.downcast_mut::<String>(handle).unwrap().push('!');
+	    assert!(store.remove(handle as *mut u64).is_none());
+	    assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| store.ptr(string))).is_err());
+	    assert_eq!(store.remove(string).as_deref(), Some("string!"));
+	    assert!(store.downcast::<String>(handle).is_none());
+	}
+	drop(store);
+	assert_eq!(Rc::strong_count(&counter), 1);
+    }
+    #[test]
+    fn scope()
+    {
+	use std::rc::Rc;
+	let counter = Rc::new(());
+	let total = Store::scope(|s| {
+	    let one = s.alloc(Some(counter.clone()));
+	    let many = s.alloc_array(3);
+	    for x in many.iter_mut() {
+		*x = one.clone();
+	    }
+	    assert_eq!(Rc::strong_count(&counter), 5);
+	    s.len()
+	});
+	assert_eq!(total, 4);
+	assert_eq!(Rc::strong_count(&counter), 1);
+
+	let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+	    Store::scope(|s| {
+		s.alloc(counter.clone());
+		panic!("unwinding");
+	    })
+	}));
+	assert!(result.is_err());
+	assert_eq!(Rc::strong_count(&counter), 1);
+    }
+    #[test]
+    fn concurrent()
+    {
+	let store = ConcurrentStore::new();
+	std::thread::scope(|scope| {
+	    for t in 0..4u32 {
+		let store = &store;
+		scope.spawn(move || {
+		    for i in 0..100 {
+			let ptr = store.alloc(t * 100 + i);
+			if i % 2 == 1 {
+			    assert!(store.remove(ptr));
+			    unsafe {
+				alloc::free(ptr as VoidPointer);
+			    }
+			}
+		    }
+		});
+	    }
+	});
+	assert_eq!(store.len(), 200);
+
+	let mut ha = store.into_heap_array();
+	ha.sort();
+	assert!(ha.iter().zip((0..400).step_by(2)).all(|(x, y)| *x == y));
+    }
+    #[test]
+    fn handles()
+    {
+	let mut store = HandleStore::new();
+	let one = store.insert("one".to_owned());
+	let two = store.insert("two".to_owned());
+	store.get_mut(two).unwrap().push('!');
+	assert_eq!(store.remove(one).as_deref(), Some("one"));
+	assert!(store.get(one).is_none());
+	assert!(store.remove(one).is_none());
+
+	let three = store.insert("three".to_owned());
+	assert_eq!(three.index, one.index);
+	assert!(!store.contains(one));
+	assert_eq!(store.get(three).map(String::as_str), Some("three"));
+	assert_eq!(store.iter().map(|(h, _)| h).collect::<Vec<_>>(), [three, two]);
+
+	let ha = store.into_heap_array();
+	assert_eq!(&ha[..], &["three", "two!"]);
+    }
+
+    #[test]
+    fn handle_retired()
+    {
+	let mut store = HandleStore::new();
+	let old = store.insert(1);
+	store.slots[old.index as usize].generation = u32::MAX;
+	let old = Handle{index: old.index, generation: u32::MAX};
+	assert_eq!(store.remove(old), Some(1));
+	let new = store.insert(2);
+	assert_ne!(new.index, old.index);
+	assert!(store.get(old).is_none());
+    }
 }
 
 impl<T> Store<T>
@@ -53,86 +252,670 @@ impl<T> Store<T>
     /// Create a new pointer store.
     pub fn new() -> Self
     {
-	Self{pointers:Vec::new()}
+	Self{pointers:Vec::new(), budget: None}
+    }
+
+    /// Create a new pointer store whose allocations are charged against `budget`.
+    ///
+    /// Pointers added with `ptr()` are not charged.
+    pub fn with_budget(budget: &Arc<Budget>) -> Self
+    {
+	Self{pointers:Vec::new(), budget: Some(Arc::clone(budget))}
+    }
+
+    /// The budget this store's allocations are charged against, if any.
+    pub fn budget(&self) -> Option<&Arc<Budget>>
+    {
+	self.budget.as_ref()
     }
 
     /// Add a pointer to the store.
     pub fn ptr(&mut self, ptr: *mut T) -> *mut T
     {
-	self.pointers.push(ptr);
+	self.pointers.push(Entry::single(ptr));
 	ptr
     }
 
+    /// Store an initialised entry and return its elements.
+    fn push_entry(&mut self, entry: Entry<T>) -> &mut [T]
+    {
+	let output = unsafe {
+	    entry.as_slice_mut()
+	};
+	self.pointers.push(entry);
+	output
+    }
+
+    /// Move `value` into memory allocated and owned by the store.
+    pub fn alloc(&mut self, value: T) -> &mut T
+    {
+	self.try_alloc(value).expect("malloc()")
+    }
+
+    /// Move `value` into memory allocated and owned by the store, or an error if allocation fails.
+    pub fn try_alloc(&mut self, value: T) -> Result<&mut T, Error>
+    {
+	let entry = Entry::alloc(1, self.budget.as_ref())?;
+	unsafe {
+	    std::ptr::write(entry.as_slice_mut().as_mut_ptr(), value);
+	}
+	Ok(&mut self.push_entry(entry)[0])
+    }
+
+    /// Allocate an array of `len` default elements owned by the store.
+    pub fn alloc_array(&mut self, len: usize) -> &mut [T]
+    where T: Default
+    {
+	self.try_alloc_array(len).expect("malloc()")
+    }
+
+    /// Allocate an array of `len` default elements owned by the store, or an error if allocation fails.
+    pub fn try_alloc_array(&mut self, len: usize) -> Result<&mut [T], Error>
+    where T: Default
+    {
+	let entry = Entry::alloc(len, self.budget.as_ref())?;
+	for slot in unsafe { entry.as_slice_mut() }.iter_mut() {
+	    unsafe {
+		std::ptr::write(slot, T::default());
+	    }
+	}
+	Ok(self.push_entry(entry))
+    }
+
+    /// Allocate an array owned by the store with the elements copied from `from`.
+    pub fn alloc_slice_copy(&mut self, from: &[T]) -> &mut [T]
+    where T: Copy
+    {
+	self.try_alloc_slice_copy(from).expect("malloc()")
+    }
+
+    /// Allocate an array owned by the store with the elements copied from `from`, or an error if allocation fails.
+    pub fn try_alloc_slice_copy(&mut self, from: &[T]) -> Result<&mut [T], Error>
+    where T: Copy
+    {
+	let entry = Entry::alloc(from.len(), self.budget.as_ref())?;
+	unsafe {
+	    entry.as_slice_mut().copy_from_slice(from);
+	}
+	Ok(self.push_entry(entry))
+    }
+
+    /// Number of elements in the store.
+    pub fn len(&self) -> usize
+    {
+	self.pointers.iter().map(|x| x.len).sum()
+    }
+
+    /// Is the store empty?
+    pub fn is_empty(&self) -> bool
+    {
+	self.len() == 0
+    }
+
     /// Remove a pointer from the store.
     pub fn remove(&mut self, ptr: *mut T)
     {
-	while let Some(i) = self.pointers.iter().position(|x| *x == ptr)
-	{
-	    self.pointers.remove(i);
-	}
+	self.pointers.retain(|x| x.ptr != ptr);
     }
 
     /// Consumes the instance and returns the pointers without freeing them.
+    ///
+    /// The number of elements allocated at each pointer is discarded.
     pub fn into_raw_parts(mut self) -> Vec<*mut T>
     {
-	std::mem::replace(&mut self.pointers, Vec::new())
+	std::mem::take(&mut self.pointers).into_iter().map(|x| x.ptr).collect()
     }
 
     /// Consume a vector of pointers and return a new `Store<T>`.
     pub fn from_raw_parts(pointers: Vec<*mut T>) -> Self
     {
-	Self {
-	    pointers,
-	}
+	pointers.into_iter().collect()
     }
     
     /// Free all the pointers in the store without calling their destructors (if the have any).
     pub fn free(mut self)
     {
-	for &mut x in self.pointers.iter_mut()
+	for x in self.pointers.drain(..)
 	{
 	    unsafe {
-		alloc::free(x as VoidPointer);
+		alloc::free(x.ptr as VoidPointer);
 	    }
 	}
-	self.pointers.clear()
     }
 
     /// Move all data from all pointers into a new `HeapArray<T>` instance and free the old pointers.
+    ///
+    /// Bytes charged against a budget stay charged, against the new instance.
     pub fn into_heap_array(mut self) -> HeapArray<T>
     {
-	let mut output = heap![T; self.pointers.len()];
-	for (mut init, old) in output.initialise().zip(std::mem::replace(&mut self.pointers, Vec::new()).into_iter())
+	let mut output = heap![T; self.len()];
+	let mut charge: Option<budget::Charge> = None;
+	let mut init = output.initialise();
+	for mut old in std::mem::take(&mut self.pointers)
 	{
 	    unsafe {
-		init.put(ptr::take(old));
-		alloc::free(old as *mut ());
+		for x in old.as_slice_mut().iter() {
+		    init.next().unwrap().put(std::ptr::read(x));
+		}
+		alloc::free(old.ptr as VoidPointer);
+	    }
+	    // The charges move to the output along with the elements.
+	    match (&mut charge, old.charge.take()) {
+		(Some(charge), Some(old)) => charge.absorb(old),
+		(None, old) => charge = old,
+		_ => (),
 	    }
 	}
+	output.charge = charge;
 	output
     }
 }
 
+impl<T> Default for Store<T>
+{
+    fn default() -> Self
+    {
+	Self::new()
+    }
+}
+
 impl<T> std::ops::Drop for Store<T>
 {
     fn drop(&mut self)
     {
-	for &mut ptr in self.pointers.iter_mut()
+	for entry in self.pointers.drain(..)
 	{
 	    unsafe {
-		drop(ptr::take(ptr));
-		alloc::free(ptr as VoidPointer);
+		entry.destroy();
 	    }
 	}
-	self.pointers.clear();
     }
 }
 
-/// Dynamically typed pointer store. Frees on drop.
+/// Allocations tied to a `Store::scope()` closure.
+///
+/// References returned are bounded by the scope, and everything allocated is dropped and freed when the closure returns, even on panic.
+pub struct Scope<'s, T>
+{
+    store: RefCell<Store<T>>,
+    _marker: PhantomData<&'s mut &'s ()>,
+}
+
+impl<T> Store<T>
+{
+    /// Run `func` with a `Scope` to allocate through. All the scope's allocations are dropped and freed when `func` returns or panics.
+    pub fn scope<F, R>(func: F) -> R
+    where F: for<'s> FnOnce(&'s Scope<'s, T>) -> R
+    {
+	let scope = Scope {
+	    store: RefCell::new(Store::new()),
+	    _marker: PhantomData,
+	};
+	func(&scope)
+    }
+}
+
+impl<'s, T> Scope<'s, T>
+{
+    /// Extend the lifetime of an entry's elements to the scope. Entries are never moved or freed before the scope ends.
+    fn extend(slice: &mut [T]) -> &'s mut [T]
+    {
+	unsafe {
+	    slice::from_raw_parts_mut(slice.as_mut_ptr(), slice.len())
+	}
+    }
+
+    /// Move `value` into memory owned by the scope.
+    pub fn alloc(&self, value: T) -> &'s mut T
+    {
+	let mut store = self.store.borrow_mut();
+	&mut Self::extend(std::slice::from_mut(store.alloc(value)))[0]
+    }
+
+    /// Allocate an array of `len` default elements owned by the scope.
+    pub fn alloc_array(&self, len: usize) -> &'s mut [T]
+    where T: Default
+    {
+	Self::extend(self.store.borrow_mut().alloc_array(len))
+    }
+
+    /// Allocate an array owned by the scope with the elements copied from `from`.
+    pub fn alloc_slice_copy(&self, from: &[T]) -> &'s mut [T]
+    where T: Copy
+    {
+	Self::extend(self.store.borrow_mut().alloc_slice_copy(from))
+    }
+
+    /// Number of elements allocated in the scope.
+    pub fn len(&self) -> usize
+    {
+	self.store.borrow().len()
+    }
+
+    /// Has nothing been allocated in the scope?
+    pub fn is_empty(&self) -> bool
+    {
+	self.store.borrow().is_empty()
+    }
+}
+
+/// Default number of shards in a `ConcurrentStore<T>`.
+const SHARDS: usize = 16;
+
+/// Thread-safe statically typed pointer store. `free()`s and drops on drop.
+///
+/// Pointers are spread over independently locked shards by address, so threads registering and releasing different pointers rarely contend.
+#[derive(Debug)]
+pub struct ConcurrentStore<T>
+{
+    shards: Box<[Mutex<Vec<Entry<T>>>]>,
+}
+
+unsafe impl<T> Send for ConcurrentStore<T>
+where T: Send{}
+unsafe impl<T> Sync for ConcurrentStore<T>
+where T: Send{}
+
+impl<T> ConcurrentStore<T>
+{
+    /// Create a new concurrent pointer store.
+    pub fn new() -> Self
+    {
+	Self::with_shards(SHARDS)
+    }
+
+    /// Create a new concurrent pointer store with `shards` locks.
+    ///
+    /// # Panics
+    /// If `shards` is 0.
+    pub fn with_shards(shards: usize) -> Self
+    {
+	assert!(shards > 0, "ConcurrentStore needs at least one shard");
+	Self {
+	    shards: (0..shards).map(|_| Mutex::new(Vec::new())).collect(),
+	}
+    }
+
+    fn shard(&self, ptr: *mut T) -> MutexGuard<'_, Vec<Entry<T>>>
+    {
+	// Allocations are at least 16 byte aligned, so the low bits carry no information.
+	let index = (ptr as usize >> 4) % self.shards.len();
+	self.shards[index].lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Add a pointer to the store.
+    pub fn ptr(&self, ptr: *mut T) -> *mut T
+    {
+	self.shard(ptr).push(Entry::single(ptr));
+	ptr
+    }
+
+    /// Move `value` into memory allocated and owned by the store, returning a pointer to it.
+    pub fn alloc(&self, value: T) -> *mut T
+    {
+	let entry = Entry::alloc(1, None).expect("malloc()");
+	unsafe {
+	    std::ptr::write(entry.as_slice_mut().as_mut_ptr(), value);
+	}
+	let ptr = entry.ptr;
+	self.shard(ptr).push(entry);
+	ptr
+    }
+
+    /// Remove a pointer from the store without freeing it. Returns `false` if it was not in the store.
+    pub fn remove(&self, ptr: *mut T) -> bool
+    {
+	let mut shard = self.shard(ptr);
+	match shard.iter().position(|x| x.ptr == ptr) {
+	    Some(i) => {
+		shard.swap_remove(i);
+		true
+	    },
+	    None => false,
+	}
+    }
+
+    /// Number of elements in the store.
+    pub fn len(&self) -> usize
+    {
+	self.shards.iter().map(|x| x.lock().unwrap_or_else(PoisonError::into_inner).iter().map(|x| x.len).sum::<usize>()).sum()
+    }
+
+    /// Is the store empty?
+    pub fn is_empty(&self) -> bool
+    {
+	self.len() == 0
+    }
+
+    /// Take the entries out of every shard.
+    fn drain(&mut self) -> Vec<Entry<T>>
+    {
+	self.shards.iter_mut().flat_map(|x| std::mem::take(x.get_mut().unwrap_or_else(PoisonError::into_inner))).collect()
+    }
+
+    /// Consumes the instance and returns the pointers without freeing them.
+    pub fn into_raw_parts(mut self) -> Vec<*mut T>
+    {
+	self.drain().into_iter().map(|x| x.ptr).collect()
+    }
+
+    /// Free all the pointers in the store without calling their destructors (if the have any).
+    pub fn free(mut self)
+    {
+	for x in self.drain()
+	{
+	    unsafe {
+		alloc::free(x.ptr as VoidPointer);
+	    }
+	}
+    }
+
+    /// Move all data from all pointers into a new `HeapArray<T>` instance and free the old pointers.
+    pub fn into_heap_array(mut self) -> HeapArray<T>
+    {
+	Store {
+	    pointers: self.drain(),
+	    budget: None,
+	}.into_heap_array()
+    }
+}
+
+impl<T> Default for ConcurrentStore<T>
+{
+    fn default() -> Self
+    {
+	Self::new()
+    }
+}
+
+impl<T> std::ops::Drop for ConcurrentStore<T>
+{
+    fn drop(&mut self)
+    {
+	for entry in self.drain()
+	{
+	    unsafe {
+		entry.destroy();
+	    }
+	}
+    }
+}
+
+impl<T> FromIterator<*mut T> for ConcurrentStore<T>
+{
+    fn from_iter<I: IntoIterator<Item=*mut T>>(iter: I) -> Self
+    {
+	let this = Self::new();
+	for ptr in iter {
+	    this.ptr(ptr);
+	}
+	this
+    }
+}
+
+/// Handle to a value in a `HandleStore<T>`. Handles to removed values are detected as stale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Handle
+{
+    index: u32,
+    generation: u32,
+}
+
+#[derive(Debug)]
+struct Slot<T>
+{
+    ptr: *mut T,
+    generation: u32,
+    occupied: bool,
+}
+
+impl<T> Slot<T>
+{
+    /// Pointer to the value. `NULL` (from `zst_noalloc`) is replaced with a dangling pointer.
+    fn value(&self) -> *mut T
+    {
+	if self.ptr == ptr::null() {
+	    std::ptr::NonNull::dangling().as_ptr()
+	} else {
+	    self.ptr
+	}
+    }
+}
+
+/// Statically typed store addressed by generational `Handle`s. `free()`s and drops on drop.
+///
+/// Insertion, removal and lookup are O(1), and removed slots are reused.
+#[derive(Debug)]
+pub struct HandleStore<T>
+{
+    slots: Vec<Slot<T>>,
+    vacant: Vec<u32>,
+    len: usize,
+}
+
+impl<T> HandleStore<T>
+{
+    /// Create a new handle store.
+    pub fn new() -> Self
+    {
+	Self {
+	    slots: Vec::new(),
+	    vacant: Vec::new(),
+	    len: 0,
+	}
+    }
+
+    /// Number of values in the store.
+    pub fn len(&self) -> usize
+    {
+	self.len
+    }
+
+    /// Is the store empty?
+    pub fn is_empty(&self) -> bool
+    {
+	self.len == 0
+    }
+
+    /// Move `value` into memory allocated and owned by the store.
+    pub fn insert(&mut self, value: T) -> Handle
+    {
+	unsafe {
+	    let ptr = alloc::malloc(std::mem::size_of::<T>()).expect("malloc()") as *mut T;
+	    if ptr != ptr::null() {
+		std::ptr::write(ptr, value);
+	    } else {
+		std::mem::forget(value);
+	    }
+	    self.insert_ptr(ptr)
+	}
+    }
+
+    /// Add a pointer to an initialised value to the store, taking ownership of it.
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by this crate's `malloc()` and point to an initialised `T`.
+    pub unsafe fn insert_ptr(&mut self, ptr: *mut T) -> Handle
+    {
+	self.len += 1;
+	if let Some(index) = self.vacant.pop() {
+	    let slot = &mut self.slots[index as usize];
+	    slot.ptr = ptr;
+	    slot.occupied = true;
+	    Handle {
+		index,
+		generation: slot.generation,
+	    }
+	} else {
+	    let index = u32::try_from(self.slots.len()).expect("too many slots");
+	    self.slots.push(Slot {
+		ptr,
+		generation: 0,
+		occupied: true,
+	    });
+	    Handle {
+		index,
+		generation: 0,
+	    }
+	}
+    }
+
+    fn slot(&self, handle: Handle) -> Option<&Slot<T>>
+    {
+	self.slots.get(handle.index as usize).filter(|x| x.occupied && x.generation == handle.generation)
+    }
+
+    /// Does `handle` refer to a value in the store?
+    pub fn contains(&self, handle: Handle) -> bool
+    {
+	self.slot(handle).is_some()
+    }
+
+    /// Get a reference to a value, or `None` if the handle is stale.
+    pub fn get(&self, handle: Handle) -> Option<&T>
+    {
+	self.slot(handle).map(|x| unsafe { &*x.value() })
+    }
+
+    /// Get a mutable reference to a value, or `None` if the handle is stale.
+    pub fn get_mut(&mut self, handle: Handle) -> Option<&mut T>
+    {
+	self.slot(handle).map(|x| unsafe { &mut *x.value() })
+    }
+
+    /// Remove a value from the store and free its memory, or `None` if the handle is stale.
+    pub fn remove(&mut self, handle: Handle) -> Option<T>
+    {
+	self.slot(handle)?;
+	let slot = &mut self.slots[handle.index as usize];
+	let value = unsafe {
+	    let value = std::ptr::read(slot.value());
+	    alloc::free(slot.ptr as VoidPointer);
+	    value
+	};
+	slot.ptr = ptr::null();
+	slot.occupied = false;
+	// A slot whose generation would wrap is retired, so old handles can never match it again.
+	if let Some(generation) = slot.generation.checked_add(1) {
+	    slot.generation = generation;
+	    self.vacant.push(handle.index);
+	}
+	self.len -= 1;
+	Some(value)
+    }
+
+    /// Iterate over the handles and values in the store.
+    pub fn iter(&self) -> impl Iterator<Item=(Handle, &T)>
+    {
+	self.slots.iter().zip(0..).filter(|(x, _)| x.occupied).map(|(x, index)| {
+	    (Handle{index, generation: x.generation}, unsafe { &*x.value() })
+	})
+    }
+
+    /// Iterate mutably over the handles and values in the store.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item=(Handle, &mut T)>
+    {
+	self.slots.iter_mut().zip(0..).filter(|(x, _)| x.occupied).map(|(x, index)| {
+	    (Handle{index, generation: x.generation}, unsafe { &mut *x.value() })
+	})
+    }
+
+    /// Move all values into a new `HeapArray<T>` instance, in slot order, and free the old pointers.
+    pub fn into_heap_array(mut self) -> HeapArray<T>
+    {
+	let mut output = heap![T; self.len];
+	let mut init = output.initialise();
+	for slot in std::mem::take(&mut self.slots).into_iter().filter(|x| x.occupied)
+	{
+	    unsafe {
+		init.next().unwrap().put(std::ptr::read(slot.value()));
+		alloc::free(slot.ptr as VoidPointer);
+	    }
+	}
+	output
+    }
+}
+
+impl<T> Default for HandleStore<T>
+{
+    fn default() -> Self
+    {
+	Self::new()
+    }
+}
+
+impl<T> std::ops::Drop for HandleStore<T>
+{
+    fn drop(&mut self)
+    {
+	for slot in self.slots.drain(..).filter(|x| x.occupied)
+	{
+	    unsafe {
+		std::ptr::drop_in_place(slot.value());
+		alloc::free(slot.ptr as VoidPointer);
+	    }
+	}
+    }
+}
+
+/// A pointer in a `DynStore` and its type-erased destructor.
+#[derive(Debug)]
+struct DynEntry
+{
+    ptr: VoidPointer,
+    drop: Option<unsafe fn(VoidPointer)>,
+    size: usize,
+    type_id: Option<TypeId>,
+}
+
+/// Pointer to a value stored in a `DynStore`. `NULL` (from `zst_noalloc`) is replaced with a dangling pointer.
+fn dyn_value<T>(ptr: VoidPointer) -> *mut T
+{
+    if ptr == ptr::NULL_PTR {
+	std::ptr::NonNull::dangling().as_ptr()
+    } else {
+	ptr as *mut T
+    }
+}
+
+unsafe fn drop_erased<T>(ptr: VoidPointer)
+{
+    std::ptr::drop_in_place(dyn_value::<T>(ptr));
+}
+
+impl DynEntry
+{
+    fn typed<T: 'static>(ptr: *mut T) -> Self
+    {
+	Self {
+	    ptr: ptr as VoidPointer,
+	    drop: Some(drop_erased::<T>),
+	    size: std::mem::size_of::<T>(),
+	    type_id: Some(TypeId::of::<T>()),
+	}
+    }
+
+    fn untyped(ptr: VoidPointer) -> Self
+    {
+	Self {
+	    ptr,
+	    drop: None,
+	    size: 0,
+	    type_id: None,
+	}
+    }
+
+    fn is<T: 'static>(&self, ptr: VoidPointer) -> bool
+    {
+	self.ptr == ptr && self.type_id == Some(TypeId::of::<T>())
+    }
+}
+
+/// Dynamically typed pointer store. Drops and frees on drop.
 #[derive(Debug)]
 pub struct DynStore
 {
-    pointers: Vec<VoidPointer>,
+    pointers: Vec<DynEntry>,
 }
 
 impl DynStore
@@ -143,33 +926,70 @@ impl DynStore
 	Self{pointers:Vec::new()}
     }
 
-    /// Add a pointer to the store.
-    pub fn ptr<T>(&mut self, ptr: *mut T) -> *mut T
+    /// Add a pointer to the store. Its destructor, size and type are recorded.
+    ///
+    /// # Safety
+    /// `ptr` must be allocated by this crate's allocator backend, and point to a `T` that is initialised before the store next accesses or drops it.
+    ///
+    /// # Panics
+    /// If `ptr` is already in the store. `NULL` (from `zst_noalloc`) can be added more than once.
+    pub unsafe fn ptr<T: 'static>(&mut self, ptr: *mut T) -> *mut T
     {
-	self.pointers.push(ptr as VoidPointer);
+	let erased = ptr as VoidPointer;
+	assert!(erased == ptr::NULL_PTR || !self.pointers.iter().any(|x| x.ptr == erased), "pointer already in the store");
+	self.pointers.push(DynEntry::typed(ptr));
 	ptr
     }
 
-    /// Remove a pointer from the store.
-    pub fn remove<T>(&mut self, ptr: *mut T)
+    fn position<T: 'static>(&self, handle: VoidPointer) -> Option<usize>
     {
-	while let Some(i) = self.pointers.iter().position(|x| *x == ptr as VoidPointer)
-	{
-	    self.pointers.remove(i);
+	self.pointers.iter().position(|x| x.is::<T>(handle))
+    }
+
+    /// Get a reference to the value at `handle` if it is in the store and is a `T`.
+    pub fn downcast<T: 'static>(&self, handle: VoidPointer) -> Option<&T>
+    {
+	self.position::<T>(handle).map(|_| unsafe { &*dyn_value::<T>(handle) })
+    }
+
+    /// Get a mutable reference to the value at `handle` if it is in the store and is a `T`.
+    pub fn downcast_mut<T: 'static>(&mut self, handle: VoidPointer) -> Option<&mut T>
+    {
+	self.position::<T>(handle).map(|_| unsafe { &mut *dyn_value::<T>(handle) })
+    }
+
+    /// The size of the value at `handle`, if it is in the store and its type is known.
+    pub fn size_of(&self, handle: VoidPointer) -> Option<usize>
+    {
+	self.pointers.iter().find(|x| x.ptr == handle && x.type_id.is_some()).map(|x| x.size)
+    }
+
+    /// Remove a `T` from the store and free its memory, returning the value.
+    ///
+    /// Returns `None` if `ptr` is not in the store or does not point to a `T`.
+    pub fn remove<T: 'static>(&mut self, ptr: *mut T) -> Option<T>
+    {
+	let entry = self.pointers.remove(self.position::<T>(ptr as VoidPointer)?);
+	unsafe {
+	    let value = std::ptr::read(dyn_value::<T>(entry.ptr));
+	    alloc::free(entry.ptr);
+	    Some(value)
 	}
     }
 
     /// Consumes the instance and returns the pointers without freeing them.
     pub fn into_raw_parts(mut self) -> Vec<*mut ()>
     {
-	std::mem::replace(&mut self.pointers, Vec::new())
+	std::mem::take(&mut self.pointers).into_iter().map(|x| x.ptr).collect()
     }
     
-    /// Consume a vector of pointers and return a new `Store<T>`.
+    /// Consume a vector of pointers and return a new `DynStore`.
+    ///
+    /// The types of these pointers are unknown, so they are freed without running destructors.
     pub fn from_raw_parts(pointers: Vec<*mut ()>) -> Self
     {
 	Self {
-	    pointers,
+	    pointers: pointers.into_iter().map(DynEntry::untyped).collect(),
 	}
     }
 
@@ -177,29 +997,37 @@ impl DynStore
     /// Free all the pointers in the store without calling their destructors (if the have any).
     pub fn free(mut self)
     {
-	for &mut x in self.pointers.iter_mut()
+	for x in self.pointers.drain(..)
 	{
 	    unsafe {
-		alloc::free(x);
+		alloc::free(x.ptr);
 	    }
 	}
-	self.pointers.clear()
     }
     
 }
 
+impl Default for DynStore
+{
+    fn default() -> Self
+    {
+	Self::new()
+    }
+}
+
 impl std::ops::Drop for DynStore
 {
     fn drop(&mut self)
     {
-	for &mut ptr in self.pointers.iter_mut()
+	for entry in self.pointers.drain(..)
 	{
 	    unsafe {
-		drop(ptr::take(ptr));
-		alloc::free(ptr);
+		if let Some(drop) = entry.drop {
+		    drop(entry.ptr);
+		}
+		alloc::free(entry.ptr);
 	    }
 	}
-	self.pointers.clear();
     }
 }
 
@@ -208,9 +1036,9 @@ impl<T> IntoIterator for Store<T>
     type Item = *mut T;
     type IntoIter = std::vec::IntoIter<Self::Item>;
 
-    fn into_iter(mut self) -> Self::IntoIter
+    fn into_iter(self) -> Self::IntoIter
     {
-	std::mem::replace(&mut self.pointers, Vec::new()).into_iter()
+	self.into_raw_parts().into_iter()
     }
 }
 
@@ -219,29 +1047,29 @@ impl IntoIterator for DynStore
     type Item = *mut ();
     type IntoIter = std::vec::IntoIter<Self::Item>;
 
-    fn into_iter(mut self) -> Self::IntoIter
+    fn into_iter(self) -> Self::IntoIter
     {
-	std::mem::replace(&mut self.pointers, Vec::new()).into_iter()
+	self.into_raw_parts().into_iter()
     }
 }
 
-use std::iter::FromIterator;
 impl<T> FromIterator<*mut T> for Store<T>
 {
     fn from_iter<I: IntoIterator<Item=*mut T>>(iter: I) -> Self
     {
 	Self {
-	    pointers: Vec::from_iter(iter)
+	    pointers: iter.into_iter().map(Entry::single).collect(),
+	    budget: None,
 	}
     }
 }
 
-impl<T> FromIterator<*mut T> for DynStore
+impl<T: 'static> FromIterator<*mut T> for DynStore
 {
     fn from_iter<I: IntoIterator<Item=*mut T>>(iter: I) -> Self
     {
 	Self {
-	    pointers: Vec::from_iter(iter.into_iter().map(|x| x as *mut ()))
+	    pointers: iter.into_iter().map(DynEntry::typed).collect()
 	}
     }
 }
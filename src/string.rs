@@ -0,0 +1,453 @@
+use super::*;
+use cstring::{
+    HeapCString,
+    NulError,
+};
+use std::{
+    cmp,
+    error,
+    hash::{
+	Hash,
+	Hasher,
+    },
+    str::Utf8Error,
+};
+
+/// A growable UTF-8 string in `malloc()`ed memory. Pushing past the capacity grows the allocation with `realloc()`.
+pub struct HeapString
+{
+    inner: HeapArray<u8>,
+    len: usize,
+}
+
+/// Bytes used to create a `HeapString` were not valid UTF-8.
+#[derive(Debug)]
+pub struct FromUtf8Error
+{
+    bytes: HeapArray<u8>,
+    error: Utf8Error,
+}
+
+impl FromUtf8Error
+{
+    /// The bytes that were not valid UTF-8.
+    pub fn into_bytes(self) -> HeapArray<u8>
+    {
+	self.bytes
+    }
+
+    /// Where the UTF-8 was invalid.
+    pub fn utf8_error(&self) -> Utf8Error
+    {
+	self.error
+    }
+}
+
+impl error::Error for FromUtf8Error{}
+impl fmt::Display for FromUtf8Error
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl HeapString
+{
+    /// Create a new empty string. Does not allocate until pushed to.
+    pub fn new() -> Self
+    {
+	Self::with_capacity(0)
+    }
+
+    /// Create a new empty string with at least `capacity` bytes allocated.
+    pub fn with_capacity(capacity: usize) -> Self
+    {
+	Self {
+	    inner: heap![unsafe u8; capacity],
+	    len: 0,
+	}
+    }
+
+    /// Create a string from bytes, checking that they are valid UTF-8.
+    ///
+    /// # Errors
+    /// If `bytes` is not valid UTF-8. The bytes can be taken back from the error.
+    pub fn from_utf8(bytes: HeapArray<u8>) -> Result<Self, FromUtf8Error>
+    {
+	match std::str::from_utf8(&bytes) {
+	    Ok(_) => Ok(Self {
+		len: bytes.len(),
+		inner: bytes,
+	    }),
+	    Err(error) => Err(FromUtf8Error{bytes, error}),
+	}
+    }
+
+    /// Create a string from bytes without checking that they are valid UTF-8.
+    ///
+    /// # Safety
+    /// `bytes` must be valid UTF-8.
+    pub unsafe fn from_utf8_unchecked(bytes: HeapArray<u8>) -> Self
+    {
+	Self {
+	    len: bytes.len(),
+	    inner: bytes,
+	}
+    }
+
+    /// Number of bytes in the string.
+    pub fn len(&self) -> usize
+    {
+	self.len
+    }
+
+    /// Is the string empty?
+    pub fn is_empty(&self) -> bool
+    {
+	self.len == 0
+    }
+
+    /// Number of bytes currently allocated.
+    pub fn capacity(&self) -> usize
+    {
+	self.inner.len()
+    }
+
+    /// As a `str`.
+    pub fn as_str(&self) -> &str
+    {
+	unsafe { std::str::from_utf8_unchecked(self.as_bytes()) }
+    }
+
+    /// As a mutable `str`.
+    pub fn as_mut_str(&mut self) -> &mut str
+    {
+	let len = self.len;
+	unsafe { std::str::from_utf8_unchecked_mut(&mut self.inner[..len]) }
+    }
+
+    /// The UTF-8 bytes of the string.
+    pub fn as_bytes(&self) -> &[u8]
+    {
+	&self.inner[..self.len]
+    }
+
+    /// Make sure at least `additional` more bytes can be pushed without reallocating, growing geometrically.
+    ///
+    /// # Errors
+    /// If allocation fails. The string is unchanged.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), Error>
+    {
+	let size = self.len.checked_add(additional).ok_or(Error::Alloc)?;
+	if size > self.inner.len() {
+	    self.inner.try_resize(cmp::max(size, self.inner.len() * 2))?;
+	}
+	Ok(())
+    }
+
+    /// Make sure at least `additional` more bytes can be pushed without reallocating, growing geometrically.
+    pub fn reserve(&mut self, additional: usize)
+    {
+	self.try_reserve(additional).expect("realloc()");
+    }
+
+    /// Shrink the allocation to the length of the string.
+    pub fn shrink_to_fit(&mut self)
+    {
+	if self.inner.len() != self.len {
+	    self.inner.try_resize(self.len).expect("realloc()");
+	}
+    }
+
+    /// Append a string slice.
+    pub fn push_str(&mut self, string: &str)
+    {
+	self.reserve(string.len());
+	self.inner[self.len..self.len + string.len()].copy_from_slice(string.as_bytes());
+	self.len += string.len();
+    }
+
+    /// Append a character.
+    pub fn push(&mut self, ch: char)
+    {
+	self.push_str(ch.encode_utf8(&mut [0; 4]));
+    }
+
+    /// Remove the last character and return it.
+    pub fn pop(&mut self) -> Option<char>
+    {
+	let ch = self.as_str().chars().next_back()?;
+	self.len -= ch.len_utf8();
+	Some(ch)
+    }
+
+    /// Shorten the string to `len` bytes. Does nothing if it is already shorter.
+    ///
+    /// # Panics
+    /// If `len` is not on a character boundary.
+    pub fn truncate(&mut self, len: usize)
+    {
+	if len < self.len {
+	    assert!(self.as_str().is_char_boundary(len), "truncate() not on a char boundary");
+	    self.len = len;
+	}
+    }
+
+    /// Remove all characters, keeping the allocation.
+    pub fn clear(&mut self)
+    {
+	self.len = 0;
+    }
+
+    /// Consumes the instance, returning the bytes shrunk to the length of the string.
+    pub fn into_heap_array(mut self) -> HeapArray<u8>
+    {
+	self.shrink_to_fit();
+	self.inner
+    }
+
+    /// Consumes the instance, appending a NUL terminator to make a `HeapCString`. Use `HeapCString::into_raw()` to pass it to C.
+    ///
+    /// The terminator is written into spare capacity without reallocating if there is any.
+    ///
+    /// # Errors
+    /// If the string contains a NUL character.
+    pub fn into_c_string(mut self) -> Result<HeapCString, NulError>
+    {
+	HeapCString::check(self.as_bytes())?;
+	self.reserve(1);
+	self.inner[self.len] = 0;
+	let (ptr, _) = self.inner.into_raw_parts();
+	unsafe {
+	    Ok(HeapCString::from_heap_array_unchecked(HeapArray::from_raw_parts(ptr, self.len + 1)))
+	}
+    }
+}
+
+impl Default for HeapString
+{
+    fn default() -> Self
+    {
+	Self::new()
+    }
+}
+
+impl Clone for HeapString
+{
+    fn clone(&self) -> Self
+    {
+	Self::from(self.as_str())
+    }
+}
+
+impl From<&str> for HeapString
+{
+    fn from(from: &str) -> Self
+    {
+	let mut output = Self::with_capacity(from.len());
+	output.push_str(from);
+	output
+    }
+}
+
+impl From<String> for HeapString
+{
+    fn from(from: String) -> Self
+    {
+	Self::from(from.as_str())
+    }
+}
+
+impl From<HeapString> for String
+{
+    fn from(from: HeapString) -> Self
+    {
+	from.as_str().to_owned()
+    }
+}
+
+impl std::str::FromStr for HeapString
+{
+    type Err = std::convert::Infallible;
+    fn from_str(from: &str) -> Result<Self, Self::Err>
+    {
+	Ok(Self::from(from))
+    }
+}
+
+impl Deref for HeapString
+{
+    type Target = str;
+    fn deref(&self) -> &Self::Target
+    {
+	self.as_str()
+    }
+}
+impl DerefMut for HeapString
+{
+    fn deref_mut(&mut self) -> &mut Self::Target
+    {
+	self.as_mut_str()
+    }
+}
+
+impl AsRef<str> for HeapString
+{
+    fn as_ref(&self) -> &str
+    {
+	self.as_str()
+    }
+}
+impl AsRef<[u8]> for HeapString
+{
+    fn as_ref(&self) -> &[u8]
+    {
+	self.as_bytes()
+    }
+}
+impl Borrow<str> for HeapString
+{
+    fn borrow(&self) -> &str
+    {
+	self.as_str()
+    }
+}
+
+impl fmt::Write for HeapString
+{
+    fn write_str(&mut self, string: &str) -> fmt::Result
+    {
+	self.push_str(string);
+	Ok(())
+    }
+    fn write_char(&mut self, ch: char) -> fmt::Result
+    {
+	self.push(ch);
+	Ok(())
+    }
+}
+
+impl<'a> Extend<&'a str> for HeapString
+{
+    fn extend<I: IntoIterator<Item = &'a str>>(&mut self, iter: I)
+    {
+	iter.into_iter().for_each(|x| self.push_str(x));
+    }
+}
+impl Extend<char> for HeapString
+{
+    fn extend<I: IntoIterator<Item = char>>(&mut self, iter: I)
+    {
+	iter.into_iter().for_each(|x| self.push(x));
+    }
+}
+
+impl PartialEq for HeapString
+{
+    fn eq(&self, other: &Self) -> bool
+    {
+	self.as_str() == other.as_str()
+    }
+}
+impl Eq for HeapString{}
+impl PartialEq<str> for HeapString
+{
+    fn eq(&self, other: &str) -> bool
+    {
+	self.as_str() == other
+    }
+}
+impl PartialEq<&str> for HeapString
+{
+    fn eq(&self, other: &&str) -> bool
+    {
+	self.as_str() == *other
+    }
+}
+impl PartialOrd for HeapString
+{
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering>
+    {
+	Some(self.cmp(other))
+    }
+}
+impl Ord for HeapString
+{
+    fn cmp(&self, other: &Self) -> cmp::Ordering
+    {
+	self.as_str().cmp(other.as_str())
+    }
+}
+impl Hash for HeapString
+{
+    fn hash<H: Hasher>(&self, state: &mut H)
+    {
+	self.as_str().hash(state);
+    }
+}
+
+impl fmt::Display for HeapString
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	fmt::Display::fmt(self.as_str(), f)
+    }
+}
+impl fmt::Debug for HeapString
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::fmt::Write;
+
+    #[test]
+    fn push()
+    {
+	let mut string = HeapString::new();
+	string.push_str("hello");
+	string.push(' ');
+	write!(string, "{}!", 42).unwrap();
+	assert_eq!(string, "hello 42!");
+	assert_eq!(string.pop(), Some('!'));
+	string.push('é');
+	assert_eq!(string.len(), 10);
+	assert!(string.capacity() >= string.len());
+	assert_eq!(string.to_uppercase(), "HELLO 42É");
+	assert_eq!(String::from(string.clone()), "hello 42é");
+	assert_eq!(&string.into_heap_array()[..], "hello 42é".as_bytes());
+    }
+
+    #[test]
+    fn utf8()
+    {
+	let bytes = heap![b'a', 0xff];
+	let error = HeapString::from_utf8(bytes).unwrap_err();
+	assert_eq!(error.utf8_error().valid_up_to(), 1);
+	assert_eq!(&error.into_bytes()[..], &[b'a', 0xff]);
+	assert_eq!(HeapString::from_utf8(heap![b'o', b'k']).unwrap(), "ok");
+    }
+
+    #[test]
+    fn c_string()
+    {
+	let mut string = HeapString::with_capacity(16);
+	string.push_str("to C");
+	let ptr = string.as_ptr();
+	let string = string.into_c_string().unwrap();
+	assert_eq!(string.as_ptr() as *const u8, ptr);
+	assert_eq!(string.as_bytes_with_nul(), b"to C\0");
+
+	let full = HeapString::from("full");
+	assert_eq!(full.capacity(), 4);
+	assert_eq!(full.into_c_string().unwrap().to_str(), Ok("full"));
+	assert_eq!(HeapString::from("a\0b").into_c_string().unwrap_err().nul_position(), 1);
+    }
+}
@@ -0,0 +1,393 @@
+use super::*;
+use std::{
+    cmp,
+    mem,
+    iter::FromIterator,
+    ptr::NonNull,
+};
+
+/// Length and capacity, stored at the start of the allocation before the elements.
+#[repr(C)]
+struct Header
+{
+    len: usize,
+    capacity: usize,
+}
+
+/// Array that is one pointer wide, with its length and capacity in a header in the same `malloc()` block as the elements.
+///
+/// The pointer is to the first element, so it can be passed to C as the data pointer and recovered with `from_raw()`.
+pub struct ThinHeapArray<T>
+{
+    ptr: NonNull<T>,
+}
+
+unsafe impl<T> Sync for ThinHeapArray<T>
+where T: Sync{}
+unsafe impl<T> Send for ThinHeapArray<T>
+where T: Send{}
+
+impl<T> ThinHeapArray<T>
+{
+    /// Bytes between the start of the allocation and the first element.
+    const fn offset() -> usize
+    {
+	let header = mem::size_of::<Header>();
+	let align = mem::align_of::<T>();
+	if align > header { align } else { header }
+    }
+
+    /// Alignment of the allocation.
+    const fn align() -> usize
+    {
+	let align = mem::align_of::<T>();
+	if align > alloc::MALLOC_ALIGN { align } else { alloc::MALLOC_ALIGN }
+    }
+
+    fn bytes_for(capacity: usize) -> Result<usize, Error>
+    {
+	capacity.checked_mul(mem::size_of::<T>())
+	    .and_then(|x| x.checked_add(Self::offset()))
+	    .ok_or(Error::Alloc)
+    }
+
+    fn header(&self) -> &Header
+    {
+	unsafe { &*((self.ptr.as_ptr() as *mut u8).sub(Self::offset()) as *const Header) }
+    }
+
+    fn header_mut(&mut self) -> &mut Header
+    {
+	unsafe { &mut *((self.ptr.as_ptr() as *mut u8).sub(Self::offset()) as *mut Header) }
+    }
+
+    /// Allocate a block for `capacity` elements and write its header.
+    unsafe fn alloc_block(capacity: usize, len: usize) -> Result<NonNull<T>, Error>
+    {
+	let bytes = Self::bytes_for(capacity)?;
+	let block = if Self::align() > alloc::MALLOC_ALIGN {
+	    alloc::aligned_malloc(Self::align(), bytes)?
+	} else {
+	    alloc::malloc(bytes)?
+	} as *mut u8;
+	(block as *mut Header).write(Header{len, capacity});
+	Ok(NonNull::new_unchecked(block.add(Self::offset()) as *mut T))
+    }
+
+    /// Create a new empty array with room for `capacity` elements.
+    ///
+    /// # Errors
+    /// If allocation fails.
+    pub fn try_with_capacity(capacity: usize) -> Result<Self, Error>
+    {
+	Ok(Self {
+	    ptr: unsafe { Self::alloc_block(capacity, 0)? },
+	})
+    }
+
+    /// Create a new empty array with room for `capacity` elements.
+    pub fn with_capacity(capacity: usize) -> Self
+    {
+	Self::try_with_capacity(capacity).expect("malloc()")
+    }
+
+    /// Create a new empty array. Only the header is allocated.
+    pub fn new_empty() -> Self
+    {
+	Self::with_capacity(0)
+    }
+
+    /// Create a new array of `size` elements from zeroed memory.
+    pub fn new(size: usize) -> Self
+    {
+	let mut output = Self::with_capacity(size);
+	unsafe {
+	    if size > 0 {
+		ptr::memset(output.ptr.as_ptr() as *mut u8, 0, size * mem::size_of::<T>());
+	    }
+	}
+	output.header_mut().len = size;
+	output
+    }
+
+    /// Number of elements in this instance.
+    pub fn len(&self) -> usize
+    {
+	self.header().len
+    }
+
+    /// Is this instance empty?
+    pub fn is_empty(&self) -> bool
+    {
+	self.len() == 0
+    }
+
+    /// Number of elements there is room for without reallocating.
+    pub fn capacity(&self) -> usize
+    {
+	self.header().capacity
+    }
+
+    /// As an immutable slice of `T`.
+    pub fn as_slice(&self) -> &[T]
+    {
+	unsafe{slice::from_raw_parts(self.ptr.as_ptr(), self.len())}
+    }
+
+    /// As a mutable slice of `T`.
+    pub fn as_slice_mut(&mut self) -> &mut [T]
+    {
+	unsafe{slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len())}
+    }
+
+    /// As immutable raw pointer to the first element.
+    pub fn as_ptr(&self) -> *const T
+    {
+	self.ptr.as_ptr() as *const T
+    }
+
+    /// As mutable raw pointer to the first element.
+    pub fn as_ptr_mut(&mut self) -> *mut T
+    {
+	self.ptr.as_ptr()
+    }
+
+    /// Make sure there is room for at least `additional` more elements, growing geometrically.
+    ///
+    /// # Errors
+    /// If allocation fails. The array is unchanged.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), Error>
+    {
+	let len = self.len();
+	let needed = len.checked_add(additional).ok_or(Error::Alloc)?;
+	if needed <= self.capacity() {
+	    return Ok(());
+	}
+	let capacity = cmp::max(needed, self.capacity() * 2);
+	unsafe {
+	    let block = (self.ptr.as_ptr() as *mut u8).sub(Self::offset()) as VoidPointer;
+	    let block = if Self::align() > alloc::MALLOC_ALIGN {
+		let new = Self::alloc_block(capacity, len)?;
+		ptr::memcpy(new.as_ptr() as VoidPointer, self.ptr.as_ptr() as ConstVoidPointer, len * mem::size_of::<T>());
+		alloc::free(block);
+		(new.as_ptr() as *mut u8).sub(Self::offset())
+	    } else {
+		alloc::realloc(block, Self::bytes_for(capacity)?)? as *mut u8
+	    };
+	    self.ptr = NonNull::new_unchecked(block.add(Self::offset()) as *mut T);
+	}
+	self.header_mut().capacity = capacity;
+	Ok(())
+    }
+
+    /// Make sure there is room for at least `additional` more elements, growing geometrically.
+    pub fn reserve(&mut self, additional: usize)
+    {
+	self.try_reserve(additional).expect("realloc()");
+    }
+
+    /// Append an element.
+    pub fn push(&mut self, value: T)
+    {
+	self.reserve(1);
+	let len = self.len();
+	unsafe {
+	    self.ptr.as_ptr().add(len).write(value);
+	}
+	self.header_mut().len = len + 1;
+    }
+
+    /// Remove the last element and return it.
+    pub fn pop(&mut self) -> Option<T>
+    {
+	let len = self.len().checked_sub(1)?;
+	self.header_mut().len = len;
+	Some(unsafe { self.ptr.as_ptr().add(len).read() })
+    }
+
+    /// Consumes the instance, returning the pointer to the first element. The length and capacity stay in the header before it.
+    ///
+    /// Give it back with `from_raw()` to free it.
+    pub fn into_raw(self) -> *mut T
+    {
+	let ptr = self.ptr.as_ptr();
+	mem::forget(self);
+	ptr
+    }
+
+    /// Recover an instance from a pointer returned by `into_raw()`, reading the length and capacity from its header.
+    ///
+    /// # Safety
+    /// `ptr` must have come from `into_raw()` on a `ThinHeapArray<T>`, and not be used again.
+    pub unsafe fn from_raw(ptr: *mut T) -> Self
+    {
+	Self {
+	    ptr: NonNull::new_unchecked(ptr),
+	}
+    }
+
+    /// Consumes the instance, moving the elements into a new `HeapArray<T>`.
+    pub fn into_heap_array(self) -> HeapArray<T>
+    {
+	let len = self.len();
+	let output = HeapArray::new_uninit(len);
+	unsafe {
+	    ptr::memcpy(output.as_ptr() as VoidPointer, self.as_ptr() as ConstVoidPointer, len * mem::size_of::<T>());
+	    alloc::free((self.into_raw() as *mut u8).sub(Self::offset()) as VoidPointer);
+	}
+	output
+    }
+}
+
+impl<T> Drop for ThinHeapArray<T>
+{
+    fn drop(&mut self)
+    {
+	unsafe {
+	    std::ptr::drop_in_place(self.as_slice_mut());
+	    alloc::free((self.ptr.as_ptr() as *mut u8).sub(Self::offset()) as VoidPointer);
+	}
+    }
+}
+
+impl<T> Default for ThinHeapArray<T>
+{
+    fn default() -> Self
+    {
+	Self::new_empty()
+    }
+}
+
+impl<T> From<HeapArray<T>> for ThinHeapArray<T>
+{
+    fn from(from: HeapArray<T>) -> Self
+    {
+	let len = from.len();
+	let mut output = Self::with_capacity(len);
+	unsafe {
+	    ptr::memcpy(output.as_ptr_mut() as VoidPointer, from.as_ptr() as ConstVoidPointer, len * mem::size_of::<T>());
+	}
+	output.header_mut().len = len;
+	from.free();
+	output
+    }
+}
+
+impl<T> From<ThinHeapArray<T>> for HeapArray<T>
+{
+    fn from(from: ThinHeapArray<T>) -> Self
+    {
+	from.into_heap_array()
+    }
+}
+
+impl<T> FromIterator<T> for ThinHeapArray<T>
+{
+    fn from_iter<I: IntoIterator<Item=T>>(iter: I) -> Self
+    {
+	let iter = iter.into_iter();
+	let mut output = Self::with_capacity(iter.size_hint().0);
+	iter.for_each(|x| output.push(x));
+	output
+    }
+}
+
+impl<T> Extend<T> for ThinHeapArray<T>
+{
+    fn extend<I: IntoIterator<Item=T>>(&mut self, iter: I)
+    {
+	iter.into_iter().for_each(|x| self.push(x));
+    }
+}
+
+impl<T> Clone for ThinHeapArray<T>
+where T: Clone
+{
+    fn clone(&self) -> Self
+    {
+	self.iter().cloned().collect()
+    }
+}
+
+impl<T> Deref for ThinHeapArray<T>
+{
+    type Target = [T];
+    fn deref(&self) -> &Self::Target
+    {
+	self.as_slice()
+    }
+}
+impl<T> DerefMut for ThinHeapArray<T>
+{
+    fn deref_mut(&mut self) -> &mut <Self as Deref>::Target
+    {
+	self.as_slice_mut()
+    }
+}
+
+impl<T> AsRef<[T]> for ThinHeapArray<T>
+{
+    fn as_ref(&self) -> &[T]
+    {
+	self.as_slice()
+    }
+}
+impl<T> AsMut<[T]> for ThinHeapArray<T>
+{
+    fn as_mut(&mut self) -> &mut [T]
+    {
+	self.as_slice_mut()
+    }
+}
+
+impl<T> fmt::Debug for ThinHeapArray<T>
+where T: fmt::Debug
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	write!(f, "{}: {:?}", std::any::type_name::<Self>(), self.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn thin()
+    {
+	assert_eq!(mem::size_of::<ThinHeapArray<String>>(), mem::size_of::<usize>());
+	assert_eq!(mem::size_of::<Option<ThinHeapArray<String>>>(), mem::size_of::<usize>());
+
+	let mut array: ThinHeapArray<_> = vec!["a".to_owned(), "b".to_owned()].into_iter().collect();
+	array.push("c".to_owned());
+	assert_eq!(&array[..], &["a", "b", "c"]);
+	assert!(array.capacity() >= 3);
+	assert_eq!(array.pop().as_deref(), Some("c"));
+
+	let raw = array.into_raw();
+	let array = unsafe { ThinHeapArray::from_raw(raw) };
+	assert_eq!(array.len(), 2);
+	assert_eq!(&array.clone().into_heap_array()[..], &["a", "b"]);
+    }
+
+    #[test]
+    fn aligned()
+    {
+	#[repr(align(64))]
+	#[derive(Debug, Clone, Copy, PartialEq)]
+	struct Aligned(u8);
+
+	let mut array = ThinHeapArray::with_capacity(1);
+	for i in 0..10 {
+	    array.push(Aligned(i));
+	    assert_eq!(array.as_ptr() as usize % 64, 0);
+	}
+	assert_eq!(array[9], Aligned(9));
+
+	let zeroed = ThinHeapArray::<u32>::new(4);
+	assert_eq!(&HeapArray::from(zeroed)[..], &[0; 4]);
+	assert!(ThinHeapArray::<u8>::new_empty().is_empty());
+    }
+}
@@ -0,0 +1,243 @@
+use super::*;
+use std::{
+    io::{
+	self,
+	Read,
+	Write,
+	BufWriter,
+    },
+    fs::File,
+    path::Path,
+    collections::HashMap,
+    convert::TryFrom,
+    time::Instant,
+    sync::{
+	Mutex,
+	PoisonError,
+	atomic::{
+	    AtomicU32,
+	    Ordering,
+	},
+    },
+};
+
+/// Magic bytes at the start of a trace file.
+const MAGIC: &[u8; 8] = b"MATRACE1";
+/// Size in bytes of an encoded `Event`.
+pub const EVENT_SIZE: usize = 40;
+
+/// An allocator call recorded in a trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind
+{
+    /// `malloc()`
+    Malloc,
+    /// `calloc()`
+    Calloc,
+    /// `realloc()` of an existing pointer.
+    Realloc,
+    /// `posix_memalign()`
+    AlignedMalloc,
+    /// `free()`
+    Free,
+}
+
+/// An allocator call recorded in a trace.
+///
+/// Pointers are identified by ids assigned in allocation order, starting at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Event
+{
+    /// Which function was called.
+    pub kind: EventKind,
+    /// Id of the thread that made the call, assigned in order of first call.
+    pub thread: u32,
+    /// Nanoseconds since tracing started.
+    pub time: u64,
+    /// Id of the pointer returned, or freed for `Free`.
+    pub id: u64,
+    /// Bytes requested, or 0 for `Free`.
+    pub size: u64,
+    /// Id of the old pointer for `Realloc`, the alignment for `AlignedMalloc`, otherwise 0.
+    pub extra: u64,
+}
+
+impl EventKind
+{
+    fn from_u8(from: u8) -> Option<Self>
+    {
+	Some(match from {
+	    0 => Self::Malloc,
+	    1 => Self::Calloc,
+	    2 => Self::Realloc,
+	    3 => Self::AlignedMalloc,
+	    4 => Self::Free,
+	    _ => return None,
+	})
+    }
+}
+
+impl Event
+{
+    /// Encode as native-endian bytes.
+    pub fn to_bytes(&self) -> [u8; EVENT_SIZE]
+    {
+	let mut bytes = [0u8; EVENT_SIZE];
+	bytes[0] = self.kind as u8;
+	bytes[4..8].copy_from_slice(&self.thread.to_ne_bytes());
+	bytes[8..16].copy_from_slice(&self.time.to_ne_bytes());
+	bytes[16..24].copy_from_slice(&self.id.to_ne_bytes());
+	bytes[24..32].copy_from_slice(&self.size.to_ne_bytes());
+	bytes[32..40].copy_from_slice(&self.extra.to_ne_bytes());
+	bytes
+    }
+
+    /// Decode from bytes written by `to_bytes()`.
+    pub fn from_bytes(bytes: &[u8; EVENT_SIZE]) -> io::Result<Self>
+    {
+	let u64_at = |at: usize| u64::from_ne_bytes(<[u8; 8]>::try_from(&bytes[at..at+8]).unwrap());
+	Ok(Self {
+	    kind: EventKind::from_u8(bytes[0]).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown trace event"))?,
+	    thread: u32::from_ne_bytes(<[u8; 4]>::try_from(&bytes[4..8]).unwrap()),
+	    time: u64_at(8),
+	    id: u64_at(16),
+	    size: u64_at(24),
+	    extra: u64_at(32),
+	})
+    }
+}
+
+#[derive(Debug)]
+struct Tracer
+{
+    output: BufWriter<File>,
+    ids: HashMap<usize, u64>,
+    next_id: u64,
+    start: Instant,
+    error: Option<io::Error>,
+}
+
+static TRACER: Mutex<Option<Tracer>> = Mutex::new(None);
+static NEXT_THREAD: AtomicU32 = AtomicU32::new(0);
+
+thread_local! {
+    static THREAD: u32 = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Start recording allocator calls from every thread to a new trace file at `path`, replacing any trace in progress.
+pub fn start<P: AsRef<Path>>(path: P) -> io::Result<()>
+{
+    let mut output = BufWriter::new(File::create(path)?);
+    output.write_all(MAGIC)?;
+    let previous = TRACER.lock().unwrap_or_else(PoisonError::into_inner).replace(Tracer {
+	output,
+	ids: HashMap::new(),
+	next_id: 1,
+	start: Instant::now(),
+	error: None,
+    });
+    if let Some(previous) = previous {
+	finish(previous)?;
+    }
+    Ok(())
+}
+
+/// Stop recording and flush the trace file.
+///
+/// # Errors
+/// If writing any of the trace failed.
+pub fn stop() -> io::Result<()>
+{
+    let tracer = TRACER.lock().unwrap_or_else(PoisonError::into_inner).take();
+    match tracer {
+	Some(tracer) => finish(tracer),
+	None => Ok(()),
+    }
+}
+
+fn finish(mut tracer: Tracer) -> io::Result<()>
+{
+    match tracer.error.take() {
+	Some(error) => Err(error),
+	None => tracer.output.flush(),
+    }
+}
+
+/// Is a trace being recorded?
+pub fn is_tracing() -> bool
+{
+    TRACER.lock().unwrap_or_else(PoisonError::into_inner).is_some()
+}
+
+/// Read all of the events from a trace file.
+pub fn read<R: Read>(mut input: R) -> io::Result<Vec<Event>>
+{
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+	return Err(io::Error::new(io::ErrorKind::InvalidData, "not an allocation trace"));
+    }
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    if bytes.len() % EVENT_SIZE != 0 {
+	return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated trace event"));
+    }
+    bytes.chunks_exact(EVENT_SIZE).map(|x| Event::from_bytes(<&[u8; EVENT_SIZE]>::try_from(x).unwrap())).collect()
+}
+
+/// Record a call that returned `ptr`. `old` is the pointer passed to `realloc()` or `free()`.
+pub(crate) fn record(kind: EventKind, ptr: VoidPointer, old: VoidPointer, size: usize, align: usize)
+{
+    let mut tracer = TRACER.lock().unwrap_or_else(PoisonError::into_inner);
+    let tracer = match tracer.as_mut() {
+	Some(tracer) => tracer,
+	None => return,
+    };
+    let old_id = tracer.ids.remove(&(old as usize)).unwrap_or(0);
+    let (id, extra) = match kind {
+	EventKind::Free if old_id == 0 => return,
+	EventKind::Free => (old_id, 0),
+	_ => {
+	    let id = tracer.next_id;
+	    tracer.next_id += 1;
+	    tracer.ids.insert(ptr as usize, id);
+	    (id, if kind == EventKind::Realloc { old_id } else { align as u64 })
+	},
+    };
+    let event = Event {
+	kind,
+	thread: THREAD.try_with(|x| *x).unwrap_or(u32::MAX),
+	time: tracer.start.elapsed().as_nanos() as u64,
+	id,
+	size: size as u64,
+	extra,
+    };
+    if tracer.error.is_none() {
+	if let Err(error) = tracer.output.write_all(&event.to_bytes()) {
+	    tracer.error = Some(error);
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn record_read()
+    {
+	let path = std::env::temp_dir().join(format!("malloc-array-trace-{}", std::process::id()));
+	start(&path).unwrap();
+	let array = HeapArray::<u32>::new(4).resize(8);
+	drop(array);
+	stop().unwrap();
+
+	let events = read(File::open(&path).unwrap()).unwrap();
+	std::fs::remove_file(&path).unwrap();
+	let events: Vec<_> = events.into_iter().filter(|x| x.thread == THREAD.with(|x| *x)).collect();
+	let at = events.iter().position(|x| (x.kind, x.size) == (EventKind::Calloc, 16)).unwrap();
+	assert_eq!((events[at+1].kind, events[at+1].size, events[at+1].extra), (EventKind::Realloc, 32, events[at].id));
+	assert_eq!((events[at+2].kind, events[at+2].id), (EventKind::Free, events[at+1].id));
+    }
+}
@@ -0,0 +1,248 @@
+use super::*;
+use std::{
+    io::{
+	self,
+	IoSlice,
+	IoSliceMut,
+    },
+    os::unix::io::RawFd,
+    cmp,
+};
+
+/// Build a list of `IoSlice`s from a collection of arrays.
+pub fn io_slices<'a, I>(arrays: I) -> Vec<IoSlice<'a>>
+where I: IntoIterator<Item=&'a HeapArray<u8>>
+{
+    arrays.into_iter().map(|x| IoSlice::new(x.as_slice())).collect()
+}
+
+/// Build a list of `IoSliceMut`s from a collection of arrays.
+pub fn io_slices_mut<'a, I>(arrays: I) -> Vec<IoSliceMut<'a>>
+where I: IntoIterator<Item=&'a mut HeapArray<u8>>
+{
+    arrays.into_iter().map(|x| IoSliceMut::new(x.as_slice_mut())).collect()
+}
+
+/// Build a raw `iovec` array from a collection of arrays.
+///
+/// The `iovec`s point into the arrays, so they must outlive any use of the output.
+pub fn iovecs<'a, I>(arrays: I) -> HeapArray<libc::iovec>
+where I: IntoIterator<Item=&'a HeapArray<u8>>
+{
+    let vec: Vec<_> = iovecs_from(arrays.into_iter().map(raw_parts), 0).collect();
+    HeapArray::from(vec)
+}
+
+/// Skip the first `skip` bytes of `buffers`, yielding `iovec`s for the rest.
+fn iovecs_from<I>(buffers: I, mut skip: usize) -> impl Iterator<Item=libc::iovec>
+where I: IntoIterator<Item=(*mut u8, usize)>
+{
+    buffers.into_iter().filter_map(move |(ptr, len)| {
+	if skip >= len {
+	    skip -= len;
+	    None
+	} else {
+	    let iov = libc::iovec {
+		iov_base: unsafe { ptr.add(skip) } as *mut libc::c_void,
+		iov_len: len - skip,
+	    };
+	    skip = 0;
+	    Some(iov)
+	}
+    })
+}
+
+fn raw_parts(array: &HeapArray<u8>) -> (*mut u8, usize)
+{
+    (array.as_ptr() as *mut u8, array.len())
+}
+
+fn raw_parts_mut(array: &mut HeapArray<u8>) -> (*mut u8, usize)
+{
+    (array.as_ptr_mut(), array.len())
+}
+
+/// Maximum number of `iovec`s a single call accepts.
+fn iov_max() -> usize
+{
+    match unsafe { libc::sysconf(libc::_SC_IOV_MAX) } {
+	n if n > 0 => n as usize,
+	_ => 1024,
+    }
+}
+
+fn total_len(arrays: &[HeapArray<u8>]) -> usize
+{
+    arrays.iter().map(|x| x.len()).sum()
+}
+
+/// Retry `func` while it is interrupted by a signal.
+fn retry<F>(mut func: F) -> io::Result<usize>
+where F: FnMut() -> isize
+{
+    loop {
+	match func() {
+	    -1 => match io::Error::last_os_error() {
+		err if err.kind() == io::ErrorKind::Interrupted => continue,
+		err => return Err(err),
+	    },
+	    n => return Ok(n as usize),
+	}
+    }
+}
+
+/// Perform one `readv()` on `fd` into `arrays`, skipping the first `skip` bytes.
+fn readv_from(fd: RawFd, arrays: &mut [HeapArray<u8>], skip: usize, offset: Option<libc::off_t>) -> io::Result<usize>
+{
+    let iov: Vec<_> = iovecs_from(arrays.iter_mut().map(raw_parts_mut), skip).take(iov_max()).collect();
+    if iov.is_empty() {
+	return Ok(0);
+    }
+    retry(|| unsafe {
+	match offset {
+	    Some(offset) => libc::preadv(fd, iov.as_ptr(), iov.len() as libc::c_int, offset),
+	    None => libc::readv(fd, iov.as_ptr(), iov.len() as libc::c_int),
+	}
+    })
+}
+
+/// Read from `fd` into `arrays` with a single `readv()` call. Returns the number of bytes read.
+pub fn readv(fd: RawFd, arrays: &mut [HeapArray<u8>]) -> io::Result<usize>
+{
+    readv_from(fd, arrays, 0, None)
+}
+
+/// Read from `fd` at `offset` into `arrays` with a single `preadv()` call. Returns the number of bytes read.
+pub fn preadv(fd: RawFd, arrays: &mut [HeapArray<u8>], offset: u64) -> io::Result<usize>
+{
+    readv_from(fd, arrays, 0, Some(offset as libc::off_t))
+}
+
+/// Write `arrays` to `fd` with a single `writev()` call. Returns the number of bytes written.
+pub fn writev(fd: RawFd, arrays: &[HeapArray<u8>]) -> io::Result<usize>
+{
+    writev_from(fd, arrays, 0)
+}
+
+fn writev_from(fd: RawFd, arrays: &[HeapArray<u8>], skip: usize) -> io::Result<usize>
+{
+    let iov: Vec<_> = iovecs_from(arrays.iter().map(raw_parts), skip).take(iov_max()).collect();
+    if iov.is_empty() {
+	return Ok(0);
+    }
+    retry(|| unsafe {
+	libc::writev(fd, iov.as_ptr(), iov.len() as libc::c_int)
+    })
+}
+
+/// Fill every array in `arrays` from `fd`, resuming after short reads.
+///
+/// # Errors
+/// `UnexpectedEof` if `fd` reaches end of file before all the arrays are filled.
+pub fn readv_exact(fd: RawFd, arrays: &mut [HeapArray<u8>]) -> io::Result<()>
+{
+    let total = total_len(arrays);
+    let mut done = 0;
+    while done < total {
+	match readv_from(fd, arrays, done, None)? {
+	    0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffers")),
+	    n => done += n,
+	}
+    }
+    Ok(())
+}
+
+/// Fill every array in `arrays` from `fd` starting at `offset`, resuming after short reads.
+///
+/// # Errors
+/// `UnexpectedEof` if `fd` reaches end of file before all the arrays are filled.
+pub fn preadv_exact(fd: RawFd, arrays: &mut [HeapArray<u8>], offset: u64) -> io::Result<()>
+{
+    let total = total_len(arrays);
+    let mut done = 0;
+    while done < total {
+	match readv_from(fd, arrays, done, Some((offset + done as u64) as libc::off_t))? {
+	    0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffers")),
+	    n => done += n,
+	}
+    }
+    Ok(())
+}
+
+/// Write every array in `arrays` to `fd`, resuming after short writes.
+///
+/// # Errors
+/// `WriteZero` if `fd` stops accepting data.
+pub fn writev_all(fd: RawFd, arrays: &[HeapArray<u8>]) -> io::Result<()>
+{
+    let total = total_len(arrays);
+    let mut done = 0;
+    while done < total {
+	match writev_from(fd, arrays, done)? {
+	    0 => return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write whole buffers")),
+	    n => done = cmp::min(total, done + n),
+	}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::{
+	io::{Write, Seek, SeekFrom},
+	os::unix::io::AsRawFd,
+    };
+
+    fn pipe() -> (RawFd, RawFd)
+    {
+	let mut fds = [0; 2];
+	assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+	(fds[0], fds[1])
+    }
+
+    #[test]
+    fn gather_scatter()
+    {
+	let (read, write) = pipe();
+	let out = [heap![1u8, 2, 3], heap![], heap![4u8, 5]];
+	writev_all(write, &out).unwrap();
+	unsafe { libc::close(write); }
+
+	let mut input = [heap![unsafe u8; 1], heap![unsafe u8; 4]];
+	readv_exact(read, &mut input).unwrap();
+	assert_eq!(&input[0][..], &[1]);
+	assert_eq!(&input[1][..], &[2,3,4,5]);
+
+	let mut more = [heap![unsafe u8; 1]];
+	assert_eq!(readv_exact(read, &mut more).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+	unsafe { libc::close(read); }
+    }
+
+    #[test]
+    fn skip_partial()
+    {
+	let arrays = [heap![1u8, 2], heap![3u8, 4, 5]];
+	let iov: Vec<_> = iovecs_from(arrays.iter().map(raw_parts), 3).collect();
+	assert_eq!(iov.len(), 1);
+	assert_eq!(iov[0].iov_len, 2);
+	assert_eq!(iov[0].iov_base as *const u8, &arrays[1][1] as *const u8);
+	assert_eq!(iovecs(&arrays).len(), 2);
+    }
+
+    #[test]
+    fn positional()
+    {
+	let path = std::env::temp_dir().join(format!("malloc-array-preadv-{}", std::process::id()));
+	let mut file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+	std::fs::remove_file(&path).unwrap();
+	file.write_all(b"abcdefgh").unwrap();
+	file.seek(SeekFrom::Start(0)).unwrap();
+
+	let mut input = [heap![unsafe u8; 2], heap![unsafe u8; 3]];
+	preadv_exact(file.as_raw_fd(), &mut input, 2).unwrap();
+	assert_eq!(&input[0][..], b"cd");
+	assert_eq!(&input[1][..], b"efg");
+    }
+}
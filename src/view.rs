@@ -0,0 +1,448 @@
+use super::*;
+use pod::Pod;
+use std::{
+    mem,
+    marker::PhantomData,
+};
+
+/// Borrowed, non-owning view of `size` elements from a raw pointer. Nothing is freed when it is dropped.
+///
+/// A `NULL` pointer is only allowed with a length of 0, and gives an empty view.
+#[derive(Clone, Copy)]
+pub struct HeapSlice<'a, T>
+{
+    ptr: *const T,
+    size: usize,
+    _marker: PhantomData<&'a [T]>,
+}
+
+/// Borrowed, non-owning mutable view of `size` elements from a raw pointer. Nothing is freed when it is dropped.
+///
+/// A `NULL` pointer is only allowed with a length of 0, and gives an empty view.
+pub struct HeapSliceMut<'a, T>
+{
+    ptr: *mut T,
+    size: usize,
+    _marker: PhantomData<&'a mut [T]>,
+}
+
+unsafe impl<T> Sync for HeapSlice<'_, T>
+where T: Sync{}
+unsafe impl<T> Send for HeapSlice<'_, T>
+where T: Sync{}
+unsafe impl<T> Sync for HeapSliceMut<'_, T>
+where T: Sync{}
+unsafe impl<T> Send for HeapSliceMut<'_, T>
+where T: Send{}
+
+/// Pointer suitable for creating slices. `NULL` is replaced with a dangling pointer.
+fn slice_ptr<T>(ptr: *mut T, size: usize) -> *mut T
+{
+    if ptr.is_null() {
+	assert_eq!(size, 0, "NULL pointer with non-zero length");
+	std::ptr::NonNull::dangling().as_ptr()
+    } else {
+	ptr
+    }
+}
+
+impl<'a, T> HeapSlice<'a, T>
+{
+    /// Create a view of `size` elements at `ptr`, borrowed for `'a`.
+    ///
+    /// # Safety
+    /// `ptr` must point to `size` initialised elements that are not mutated or freed for `'a`.
+    ///
+    /// # Panics
+    /// If `ptr` is `NULL` and `size` is not 0.
+    pub unsafe fn from_raw_parts(ptr: *const T, size: usize) -> Self
+    {
+	Self {
+	    ptr: slice_ptr(ptr as *mut T, size),
+	    size,
+	    _marker: PhantomData,
+	}
+    }
+
+    /// Number of elements in this view.
+    pub fn len(&self) -> usize
+    {
+	self.size
+    }
+
+    /// Is this view empty?
+    pub fn is_empty(&self) -> bool
+    {
+	self.size == 0
+    }
+
+    /// Size of memory of this view in bytes.
+    pub fn len_bytes(&self) -> usize
+    {
+	mem::size_of::<T>() * self.size
+    }
+
+    /// As an immutable slice of `T`, for the whole of `'a`.
+    pub fn as_slice(&self) -> &'a [T]
+    {
+	unsafe{slice::from_raw_parts(self.ptr, self.size)}
+    }
+
+    /// As immutable raw pointer. Dangling if the view was created from `NULL`.
+    pub fn as_ptr(&self) -> *const T
+    {
+	self.ptr
+    }
+
+    /// An immutable slice of the memory.
+    pub fn memory(&self) -> &'a [u8]
+    where T: Pod
+    {
+	unsafe{slice::from_raw_parts(self.ptr as *const u8, self.len_bytes())}
+    }
+
+    /// Reinterpret the memory of this view into an immutable slice of a different type.
+    /// # Panics
+    /// If `U` is zero-sized, cannot fit into `T`, or the view is not aligned for `U`.
+    pub fn reinterpret_ref<U: Pod>(&self) -> &'a [U]
+    where T: Pod
+    {
+	assert!(mem::size_of::<U>() != 0);
+	assert!(self.len_bytes().is_multiple_of(mem::size_of::<U>()));
+	assert!((self.ptr as usize).is_multiple_of(mem::align_of::<U>()), "view is not aligned for the target type");
+	unsafe {
+	    slice::from_raw_parts(self.ptr as *const U, self.len_bytes() / mem::size_of::<U>())
+	}
+    }
+
+    /// Immutable slice iterator for this view.
+    pub fn iter(&self) -> slice::Iter<'a, T>
+    {
+	self.as_slice().iter()
+    }
+
+    /// Copy the elements into a new owned `HeapArray<T>`.
+    pub fn to_heap_array(&self) -> HeapArray<T>
+    where T: Copy
+    {
+	unsafe {
+	    HeapArray::from_raw_copied(self.ptr, self.size)
+	}
+    }
+}
+
+impl<'a, T> HeapSliceMut<'a, T>
+{
+    /// Create a mutable view of `size` elements at `ptr`, borrowed for `'a`.
+    ///
+    /// # Safety
+    /// `ptr` must point to `size` initialised elements that are not otherwise accessed or freed for `'a`.
+    ///
+    /// # Panics
+    /// If `ptr` is `NULL` and `size` is not 0.
+    pub unsafe fn from_raw_parts(ptr: *mut T, size: usize) -> Self
+    {
+	Self {
+	    ptr: slice_ptr(ptr, size),
+	    size,
+	    _marker: PhantomData,
+	}
+    }
+
+    /// Number of elements in this view.
+    pub fn len(&self) -> usize
+    {
+	self.size
+    }
+
+    /// Is this view empty?
+    pub fn is_empty(&self) -> bool
+    {
+	self.size == 0
+    }
+
+    /// Size of memory of this view in bytes.
+    pub fn len_bytes(&self) -> usize
+    {
+	mem::size_of::<T>() * self.size
+    }
+
+    /// As an immutable slice of `T`.
+    pub fn as_slice(&self) -> &[T]
+    {
+	unsafe{slice::from_raw_parts(self.ptr, self.size)}
+    }
+
+    /// As a mutable slice of `T`.
+    pub fn as_slice_mut(&mut self) -> &mut [T]
+    {
+	unsafe{slice::from_raw_parts_mut(self.ptr, self.size)}
+    }
+
+    /// Consumes the view, returning a mutable slice for the whole of `'a`.
+    pub fn into_slice(self) -> &'a mut [T]
+    {
+	unsafe{slice::from_raw_parts_mut(self.ptr, self.size)}
+    }
+
+    /// As immutable raw pointer. Dangling if the view was created from `NULL`.
+    pub fn as_ptr(&self) -> *const T
+    {
+	self.ptr as *const T
+    }
+
+    /// As mutable raw pointer. Dangling if the view was created from `NULL`.
+    pub fn as_ptr_mut(&mut self) -> *mut T
+    {
+	self.ptr
+    }
+
+    /// An immutable view borrowing this one.
+    pub fn as_heap_slice(&self) -> HeapSlice<'_, T>
+    {
+	unsafe {
+	    HeapSlice::from_raw_parts(self.ptr, self.size)
+	}
+    }
+
+    /// An immutable slice of the memory.
+    pub fn memory(&self) -> &[u8]
+    where T: Pod
+    {
+	unsafe{slice::from_raw_parts(self.ptr as *const u8, self.len_bytes())}
+    }
+
+    /// A mutable slice of the memory.
+    ///
+    /// # Safety
+    /// Any bytes written must leave the elements valid values of `T`.
+    pub unsafe fn memory_mut(&mut self) -> &mut [u8]
+    {
+	slice::from_raw_parts_mut(self.ptr as *mut u8, self.len_bytes())
+    }
+
+    /// Reinterpret the memory of this view into an immutable slice of a different type.
+    /// # Panics
+    /// If `U` is zero-sized, cannot fit into `T`, or the view is not aligned for `U`.
+    pub fn reinterpret_ref<U: Pod>(&self) -> &[U]
+    where T: Pod
+    {
+	self.as_heap_slice().reinterpret_ref()
+    }
+
+    /// Reinterpret the memory of this view into a mutable slice of a different type.
+    /// # Panics
+    /// If `U` cannot fit into `T`.
+    /// # Safety
+    /// The memory must be valid and aligned for `U`, and any writes must leave the elements valid values of `T`.
+    pub unsafe fn reinterpret_mut<U>(&mut self) -> &mut [U]
+    {
+	assert!(self.len_bytes().is_multiple_of(mem::size_of::<U>()));
+	slice::from_raw_parts_mut(self.ptr as *mut U, self.len_bytes() / mem::size_of::<U>())
+    }
+
+    /// Immutable slice iterator for this view.
+    pub fn iter(&self) -> slice::Iter<'_, T>
+    {
+	self.as_slice().iter()
+    }
+
+    /// Mutable slice iterator for this view.
+    pub fn iter_mut(&mut self) -> slice::IterMut<'_, T>
+    {
+	self.as_slice_mut().iter_mut()
+    }
+
+    /// Copy the elements into a new owned `HeapArray<T>`.
+    pub fn to_heap_array(&self) -> HeapArray<T>
+    where T: Copy
+    {
+	self.as_heap_slice().to_heap_array()
+    }
+}
+
+impl<T> HeapArray<T>
+{
+    /// A non-owning view of the elements.
+    pub fn as_heap_slice(&self) -> HeapSlice<'_, T>
+    {
+	unsafe {
+	    HeapSlice::from_raw_parts(self.as_ptr(), self.len())
+	}
+    }
+
+    /// A non-owning mutable view of the elements.
+    pub fn as_heap_slice_mut(&mut self) -> HeapSliceMut<'_, T>
+    {
+	let size = self.len();
+	unsafe {
+	    HeapSliceMut::from_raw_parts(self.as_ptr_mut(), size)
+	}
+    }
+}
+
+impl<'a, T> From<&'a [T]> for HeapSlice<'a, T>
+{
+    fn from(from: &'a [T]) -> Self
+    {
+	unsafe {
+	    Self::from_raw_parts(from.as_ptr(), from.len())
+	}
+    }
+}
+impl<'a, T> From<&'a mut [T]> for HeapSliceMut<'a, T>
+{
+    fn from(from: &'a mut [T]) -> Self
+    {
+	unsafe {
+	    Self::from_raw_parts(from.as_mut_ptr(), from.len())
+	}
+    }
+}
+
+impl<T> Deref for HeapSlice<'_, T>
+{
+    type Target = [T];
+    fn deref(&self) -> &Self::Target
+    {
+	self.as_slice()
+    }
+}
+impl<T> Deref for HeapSliceMut<'_, T>
+{
+    type Target = [T];
+    fn deref(&self) -> &Self::Target
+    {
+	self.as_slice()
+    }
+}
+impl<T> DerefMut for HeapSliceMut<'_, T>
+{
+    fn deref_mut(&mut self) -> &mut <Self as Deref>::Target
+    {
+	self.as_slice_mut()
+    }
+}
+
+impl<T> AsRef<[T]> for HeapSlice<'_, T>
+{
+    fn as_ref(&self) -> &[T]
+    {
+	self.as_slice()
+    }
+}
+impl<T> AsRef<[T]> for HeapSliceMut<'_, T>
+{
+    fn as_ref(&self) -> &[T]
+    {
+	self.as_slice()
+    }
+}
+impl<T> AsMut<[T]> for HeapSliceMut<'_, T>
+{
+    fn as_mut(&mut self) -> &mut [T]
+    {
+	self.as_slice_mut()
+    }
+}
+
+impl<T, I> Index<I> for HeapSlice<'_, T>
+where I: SliceIndex<[T]>
+{
+    type Output = <I as SliceIndex<[T]>>::Output;
+    fn index(&self, index: I) -> &Self::Output
+    {
+	&self.as_slice()[index]
+    }
+}
+impl<T, I> Index<I> for HeapSliceMut<'_, T>
+where I: SliceIndex<[T]>
+{
+    type Output = <I as SliceIndex<[T]>>::Output;
+    fn index(&self, index: I) -> &Self::Output
+    {
+	&self.as_slice()[index]
+    }
+}
+impl<T, I> IndexMut<I> for HeapSliceMut<'_, T>
+where I: SliceIndex<[T]>
+{
+    fn index_mut(&mut self, index: I) -> &mut <Self as Index<I>>::Output
+    {
+	&mut self.as_slice_mut()[index]
+    }
+}
+
+impl<'a, T> IntoIterator for HeapSlice<'a, T>
+{
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter
+    {
+	self.iter()
+    }
+}
+impl<'a, T> IntoIterator for HeapSliceMut<'a, T>
+{
+    type Item = &'a mut T;
+    type IntoIter = slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter
+    {
+	self.into_slice().iter_mut()
+    }
+}
+
+impl<T> fmt::Debug for HeapSlice<'_, T>
+where T: fmt::Debug
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	write!(f, "{}: {:?}", std::any::type_name::<Self>(), self.as_slice())
+    }
+}
+impl<T> fmt::Debug for HeapSliceMut<'_, T>
+where T: fmt::Debug
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	write!(f, "{}: {:?}", std::any::type_name::<Self>(), self.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn views()
+    {
+	let mut array = heap![1u16, 2, 3, 4];
+	let view = unsafe { HeapSlice::from_raw_parts(array.as_ptr(), 2) };
+	assert_eq!(&view[..], &[1, 2]);
+	assert_eq!(view.memory().len(), 4);
+	assert_eq!(view.reinterpret_ref::<u32>().len(), 1);
+	let bytes = [0u8; 8];
+	let misaligned = HeapSlice::from(&bytes[(bytes.as_ptr() as usize).is_multiple_of(4) as usize..][..4]);
+	assert!(std::panic::catch_unwind(|| misaligned.reinterpret_ref::<u32>().len()).is_err());
+	assert_eq!(view.into_iter().sum::<u16>(), 3);
+	let owned = view.to_heap_array();
+	assert_eq!(&owned[..], &[1, 2]);
+
+	let mut view = array.as_heap_slice_mut();
+	view.iter_mut().for_each(|x| *x *= 10);
+	view[3] = 0;
+	assert_eq!(&array[..], &[10, 20, 30, 0]);
+    }
+
+    #[test]
+    fn null()
+    {
+	let view = unsafe { HeapSlice::<u64>::from_raw_parts(ptr::null(), 0) };
+	assert!(view.is_empty() && view.memory().is_empty());
+	assert!(view.to_heap_array().is_empty());
+	assert!(std::panic::catch_unwind(|| unsafe { HeapSliceMut::<u8>::from_raw_parts(ptr::null(), 1) }).is_err());
+    }
+}
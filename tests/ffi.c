@@ -0,0 +1,41 @@
+/* Exercises include/malloc_array.h. Compiled by build.rs with the `ffi` feature and linked only into tests/ffi.rs. */
+#include <stdint.h>
+#include <string.h>
+
+#include "malloc_array.h"
+
+#define CHECK(x) do { if (!(x)) return __LINE__; } while (0)
+
+/* Checks the C interface, then grows `*from_rust` (3 zeroed `uint32_t`s) to 8 elements holding 0..7. */
+int malloc_array_c_test(malloc_array_t* from_rust)
+{
+	malloc_array_t array;
+	CHECK(malloc_array_create_zeroed(sizeof(uint64_t), 4, &array) == 0);
+	CHECK(malloc_array_len(&array) == 4 && array.element_size == sizeof(uint64_t));
+	uint64_t* data = malloc_array_data(&array);
+	CHECK(data[0] == 0 && data[3] == 0);
+	data[3] = 42;
+
+	CHECK(malloc_array_resize(&array, 16) == 0);
+	CHECK(((uint64_t*)malloc_array_data(&array))[3] == 42);
+	CHECK(malloc_array_resize(&array, SIZE_MAX) == -1 && malloc_array_len(&array) == 16);
+
+	size_t len = 0;
+	void* raw = malloc_array_into_raw(&array, &len);
+	CHECK(len == 16 && malloc_array_len(&array) == 0 && malloc_array_data(&array) == NULL);
+	array = malloc_array_from_raw(raw, sizeof(uint64_t), len);
+	malloc_array_free(&array);
+	CHECK(array.data == NULL && array.len == 0);
+	malloc_array_free(NULL);
+
+	CHECK(malloc_array_create(sizeof(char), 6, &array) == 0);
+	memcpy(malloc_array_data(&array), "hello", 6);
+	CHECK(strcmp(malloc_array_data(&array), "hello") == 0);
+	malloc_array_free(&array);
+
+	CHECK(malloc_array_len(from_rust) == 3 && from_rust->element_size == sizeof(uint32_t));
+	CHECK(malloc_array_resize(from_rust, 8) == 0);
+	uint32_t* ints = malloc_array_data(from_rust);
+	for (uint32_t i = 0; i < 8; i++) ints[i] = i;
+	return 0;
+}
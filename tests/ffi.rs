@@ -0,0 +1,18 @@
+use malloc_array::{
+    heap,
+    ffi::*,
+};
+use std::os::raw::c_int;
+
+extern "C" {
+    fn malloc_array_c_test(array: *mut MallocArray) -> c_int;
+}
+
+#[test]
+fn c_test()
+{
+    let mut array = heap![u32; 3].into_ffi();
+    assert_eq!(unsafe { malloc_array_c_test(&mut array) }, 0);
+    let array = unsafe { array.into_heap_array::<u32>() }.unwrap();
+    assert_eq!(&array[..], &[0, 1, 2, 3, 4, 5, 6, 7]);
+}